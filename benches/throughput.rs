@@ -0,0 +1,131 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use payment_engine::{initialize, process_reader, Config, InputConfig};
+use std::{fmt::Write, io, time::Duration};
+use tokio::runtime::Runtime;
+
+/// Number of deposits, withdrawals and disputes of every generated file
+const ROWS: usize = 100_000;
+
+/// Number of distinct clients of every generated file
+const CLIENTS: u64 = 1_000;
+
+/// Seed of the generated files
+const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// Deterministic pseudo random numbers (xorshift), the same seed always
+/// generates the same file
+struct Generator(u64);
+
+impl Generator {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a CSV file of `rows` transactions, `disputes` out of 100 of them
+    /// dispute a past deposit of the same client which is then resolved or
+    /// charged back
+    fn transactions(&mut self, rows: usize, disputes: u64) -> String {
+        let mut csv = String::from("type,client,tx,amount\n");
+        let mut deposits: Vec<(u64, u32)> = vec![];
+        let mut tx_id = 0u32;
+
+        for _ in 0..rows {
+            let roll = self.next() % 100;
+            if roll < disputes && !deposits.is_empty() {
+                let (client, tx) = deposits[(self.next() % deposits.len() as u64) as usize];
+                let outcome = if self.next().is_multiple_of(4) {
+                    "chargeback"
+                } else {
+                    "resolve"
+                };
+                let _ = writeln!(csv, "dispute,{client},{tx}\n{outcome},{client},{tx}");
+                continue;
+            }
+
+            tx_id += 1;
+            let client = self.next() % CLIENTS + 1;
+            let amount = format!("{}.{:04}", self.next() % 1_000, self.next() % 10_000);
+            // one transaction out of five is a withdrawal
+            if roll.is_multiple_of(5) {
+                let _ = writeln!(csv, "withdrawal,{client},{tx_id},{amount}");
+            } else {
+                let _ = writeln!(csv, "deposit,{client},{tx_id},{amount}");
+                deposits.push((client, tx_id));
+            }
+        }
+        csv
+    }
+}
+
+fn bench_workload(c: &mut Criterion, name: &str, disputes: u64) {
+    let runtime = Runtime::new().unwrap();
+    let data = Generator(SEED).transactions(ROWS, disputes);
+    let config = Config::default();
+
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+    group.bench_function("process_reader", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(process_reader(
+                    InputConfig::default().from_reader(data.as_bytes()),
+                    io::sink(),
+                    &config,
+                ))
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+/// Mostly deposits and withdrawals
+fn deposit_heavy(c: &mut Criterion) {
+    bench_workload(c, "deposit_heavy", 2);
+}
+
+/// Close to half of the rows dispute a past deposit
+fn dispute_heavy(c: &mut Criterion) {
+    bench_workload(c, "dispute_heavy", 45);
+}
+
+/// Reads the same file through a buffer and from a memory-mapped slice
+fn mmap(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let data = Generator(SEED).transactions(ROWS, 2);
+    let path =
+        std::env::temp_dir().join(format!("payment_engine_bench_{}.csv", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+
+    let mut group = c.benchmark_group("mmap");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+    for (name, mmap) in [("buffered", false), ("mmap", true)] {
+        let config = Config {
+            inputs: vec![path.clone()],
+            mmap,
+            ..Default::default()
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                runtime
+                    .block_on(process_reader(
+                        initialize(&config).unwrap(),
+                        io::sink(),
+                        &config,
+                    ))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+    let _ = std::fs::remove_file(&path);
+}
+
+criterion_group!(benches, deposit_heavy, dispute_heavy, mmap);
+criterion_main!(benches);
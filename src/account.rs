@@ -0,0 +1,113 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::{config::Config, output::Column};
+
+/// Snapshot of a client's account balances, accounts are ordered by client ID
+/// first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Account {
+    /// Client ID
+    pub client: u16,
+    /// Available balance
+    #[serde(with = "rust_decimal::serde::str")]
+    pub available: Decimal,
+    /// Held balance
+    #[serde(with = "rust_decimal::serde::str")]
+    pub held: Decimal,
+    /// Total balance
+    #[serde(with = "rust_decimal::serde::str")]
+    pub total: Decimal,
+    /// Flag indicating if account is frozen (chargeback)
+    pub locked: bool,
+    /// ID of the most recent deposit or withdrawal, useful for debugging
+    #[serde(default)]
+    pub last_tx_id: u32,
+    /// Number of transactions applied to the account, disputes, resolves and
+    /// chargebacks included
+    #[serde(default)]
+    pub transactions: u64,
+}
+
+impl Account {
+    /// Retrieves client's account infomation formatted as an output record,
+    /// the client ID followed by the given columns. The amounts have
+    /// the configured number of decimal places and separator
+    pub(crate) fn get_info(&self, config: &Config, columns: &[Column]) -> Vec<String> {
+        let format = |amount| {
+            let amount = format_scaled(amount, config.policy.decimal_places);
+            match config.decimal_separator {
+                '.' => amount,
+                separator => amount.replace('.', &separator.to_string()),
+            }
+        };
+        let mut info = vec![self.client.to_string()];
+        info.extend(columns.iter().map(|column| match column {
+            Column::Available => format(self.available),
+            Column::Held => format(self.held),
+            Column::Total => format(self.total),
+            Column::Locked => self.locked.to_string(),
+            Column::Transactions => self.transactions.to_string(),
+        }));
+        info
+    }
+}
+
+/// Balance changes of a client between two engine states, a client missing
+/// from one of them has no balance there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountDiff {
+    /// Client ID
+    pub client: u16,
+    /// Change of the available balance
+    pub available: Decimal,
+    /// Change of the held balance
+    pub held: Decimal,
+    /// Change of the total balance
+    pub total: Decimal,
+    /// Flag indicating if the account was frozen or unfrozen
+    pub locked_changed: bool,
+}
+
+/// Balances summed across every client account, a quick solvency check
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SystemTotals {
+    /// Sum of the available balances
+    pub total_available: Decimal,
+    /// Sum of the held balances
+    pub total_held: Decimal,
+    /// Sum of the total balances
+    pub total: Decimal,
+}
+
+impl fmt::Display for SystemTotals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "available: {}, held: {}, total: {}",
+            format_amount(self.total_available),
+            format_amount(self.total_held),
+            format_amount(self.total)
+        )
+    }
+}
+
+/// Number of decimal places of the amounts when no minor unit is configured
+pub(crate) const DECIMAL_PLACES: u32 = 4;
+
+/// Formats an amount with four decimal places, rounding rather than truncating
+/// the extra ones
+pub(crate) fn format_amount(amount: Decimal) -> String {
+    format_scaled(amount, DECIMAL_PLACES)
+}
+
+/// Formats an amount with the given number of decimal places, rounding rather
+/// than truncating the extra ones
+pub(crate) fn format_scaled(amount: Decimal, decimal_places: u32) -> String {
+    format!(
+        "{:.*}",
+        decimal_places as usize,
+        amount.round_dp(decimal_places)
+    )
+}
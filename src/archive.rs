@@ -0,0 +1,43 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::{io::Read, path::Path};
+use tar::{Archive, EntryType};
+
+use crate::{config::Config, input::Input, input::LimitReader};
+
+/// Returns `true` if the file is a gzip compressed tar archive, e.g.
+/// `ledger.tar.gz` or `ledger.tgz`
+pub(crate) fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
+}
+
+/// Parses every file of a gzip compressed tar archive in archive order, `read`
+/// receives the transactions parser of every file and returns `false` to stop.
+///
+/// The archive is decompressed while it's read, a single file is buffered at a
+/// time by it's parser
+pub(crate) fn read_entries<F>(path: &Path, config: &Config, mut read: F) -> Result<()>
+where
+    F: FnMut(Input<Box<dyn Read + '_>>) -> Result<bool>,
+{
+    let mut archive = Archive::new(GzDecoder::new(crate::open(path, config)?));
+    let entries = archive
+        .entries()
+        .with_context(|| format!("Unable to read archive {}", path.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Unable to read archive {}", path.display()))?;
+        // directories and links don't hold transactions
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        let file: Box<dyn Read> = match config.max_record_size {
+            Some(limit) => Box::new(LimitReader::new(entry, limit)),
+            None => Box::new(entry),
+        };
+        if !read(crate::configure(crate::parse(file, config), config))? {
+            break;
+        }
+    }
+    Ok(())
+}
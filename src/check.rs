@@ -0,0 +1,125 @@
+use anyhow::Result;
+use std::{collections::HashSet, fmt, io::Read};
+
+use crate::{input::Input, transaction::TransactionEnum};
+
+/// Statistics about the structure of a transactions file, gathered without
+/// computing any balance
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileStats {
+    /// Number of transactions
+    pub rows: u64,
+    /// Number of deposits
+    pub deposits: u64,
+    /// Number of withdrawals
+    pub withdrawals: u64,
+    /// Number of disputes
+    pub disputes: u64,
+    /// Number of resolves
+    pub resolves: u64,
+    /// Number of chargebacks
+    pub chargebacks: u64,
+    /// Number of reversals
+    pub reversals: u64,
+    /// Number of transfers
+    pub transfers: u64,
+    /// Number of end of client markers
+    pub end_clients: u64,
+    /// Smallest client ID, `None` for an empty file
+    pub min_client: Option<u16>,
+    /// Largest client ID, `None` for an empty file
+    pub max_client: Option<u16>,
+    /// Flag indicating if a deposit or withdrawal reuses a transaction ID
+    pub repeated_tx_ids: bool,
+}
+
+impl FileStats {
+    /// Reads every transaction of the input and collects it's statistics
+    pub fn collect<R: Read>(input: impl Into<Input<R>>) -> Result<Self> {
+        let mut input = input.into();
+        let mut stats = Self::default();
+        let mut tx_ids = HashSet::new();
+
+        while let Some(tx) = input.read_tx()? {
+            stats.rows += 1;
+            match tx.tx_type {
+                TransactionEnum::Deposit => stats.deposits += 1,
+                TransactionEnum::Withdrawal => stats.withdrawals += 1,
+                TransactionEnum::Dispute => stats.disputes += 1,
+                TransactionEnum::Resolve => stats.resolves += 1,
+                TransactionEnum::Chargeback => stats.chargebacks += 1,
+                TransactionEnum::Reversal => stats.reversals += 1,
+                TransactionEnum::Transfer => stats.transfers += 1,
+                TransactionEnum::EndClient => stats.end_clients += 1,
+            }
+            stats.min_client = Some(
+                stats
+                    .min_client
+                    .map_or(tx.client_id, |min| min.min(tx.client_id)),
+            );
+            stats.max_client = stats.max_client.max(Some(tx.client_id));
+            // disputes, resolves and chargebacks reference a past transaction ID
+            if matches!(
+                tx.tx_type,
+                TransactionEnum::Deposit | TransactionEnum::Withdrawal | TransactionEnum::Transfer
+            ) && !tx_ids.insert(tx.tx_id)
+            {
+                stats.repeated_tx_ids = true;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+impl fmt::Display for FileStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let client = |client: Option<u16>| client.map_or("-".to_string(), |id| id.to_string());
+        writeln!(f, "rows: {}", self.rows)?;
+        writeln!(f, "deposit: {}", self.deposits)?;
+        writeln!(f, "withdrawal: {}", self.withdrawals)?;
+        writeln!(f, "dispute: {}", self.disputes)?;
+        writeln!(f, "resolve: {}", self.resolves)?;
+        writeln!(f, "chargeback: {}", self.chargebacks)?;
+        writeln!(f, "reversal: {}", self.reversals)?;
+        writeln!(f, "transfer: {}", self.transfers)?;
+        writeln!(f, "end_client: {}", self.end_clients)?;
+        writeln!(f, "min client: {}", client(self.min_client))?;
+        writeln!(f, "max client: {}", client(self.max_client))?;
+        writeln!(f, "repeated tx ids: {}", self.repeated_tx_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileStats;
+    use crate::config::InputConfig;
+    use std::fs::File;
+
+    #[test]
+    fn file_stats() {
+        let file = File::open("csv_files/balance_test.csv").unwrap();
+        let stats = FileStats::collect(InputConfig::default().from_reader(file)).unwrap();
+        assert_eq!(
+            stats,
+            FileStats {
+                rows: 10,
+                deposits: 5,
+                withdrawals: 2,
+                disputes: 2,
+                resolves: 0,
+                chargebacks: 1,
+                reversals: 0,
+                transfers: 0,
+                end_clients: 0,
+                min_client: Some(1),
+                max_client: Some(2),
+                repeated_tx_ids: true,
+            }
+        );
+
+        // disputes referencing a deposit don't count as a repeated ID
+        let file = File::open("csv_files/tx_test.csv").unwrap();
+        let stats = FileStats::collect(InputConfig::default().from_reader(file)).unwrap();
+        assert_eq!((stats.rows, stats.repeated_tx_ids), (5, false));
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::engine::PaymentEngine;
+
+/// Magic bytes starting a checkpoint, followed by the version of its layout
+const MAGIC: [u8; 4] = *b"PECK";
+
+/// Version of the layout of the checkpoint, a checkpoint of another version
+/// isn't resumed
+const VERSION: u8 = 1;
+
+/// State of a run after a number of input records, a killed run resumes from
+/// it by skipping the records already applied
+///
+/// `E` is the engine, borrowed when a checkpoint is saved
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Checkpoint<E = PaymentEngine> {
+    /// Number of input records read, including the ones skipped
+    pub(crate) records: u64,
+    /// Number of transactions applied successfully
+    pub(crate) processed: u64,
+    /// Number of transactions that failed to be applied
+    pub(crate) rejected: u64,
+    /// Client accounts after the records were applied
+    pub(crate) engine: E,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint of a previous run, `None` if there is none
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(path)
+            .with_context(|| format!("Unable to open checkpoint {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+        let mut header = [0; 5];
+        reader
+            .read_exact(&mut header)
+            .with_context(|| format!("Unable to decode checkpoint {}", path.display()))?;
+        if header[..4] != MAGIC || header[4] != VERSION {
+            bail!(
+                "Checkpoint {} was saved by another version, remove it to start the run over",
+                path.display()
+            );
+        }
+        let checkpoint = bincode::deserialize_from(reader)
+            .with_context(|| format!("Unable to decode checkpoint {}", path.display()))?;
+        Ok(Some(checkpoint))
+    }
+}
+
+impl Checkpoint<&PaymentEngine> {
+    /// Saves the checkpoint, it's written next to `path` first and then
+    /// renamed so a run killed while saving leaves the previous one intact
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("partial");
+        let mut writer = BufWriter::new(
+            File::create(&partial)
+                .with_context(|| format!("Unable to create checkpoint {}", partial.display()))?,
+        );
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        bincode::serialize_into(&mut writer, self)
+            .with_context(|| format!("Unable to encode checkpoint {}", path.display()))?;
+        writer.flush()?;
+        fs::rename(&partial, path)
+            .with_context(|| format!("Unable to save checkpoint {}", path.display()))
+    }
+}
@@ -1,63 +1,114 @@
 use anyhow::{anyhow, bail, Result};
-use std::collections::HashMap;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use tinyset::SetU32;
 
-use crate::transaction::TransactionEnum;
+use crate::{
+    account::Account,
+    policy::{Policy, WithdrawalDispute},
+    transaction::TransactionEnum,
+};
 
-#[derive(Debug, PartialEq, Clone)]
+/// Step of the lifecycle of a disputed transaction, holding it's ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisputeEvent {
+    /// The transaction was disputed
+    Opened(u32),
+    /// The dispute was resolved
+    Resolved(u32),
+    /// The dispute was charged back
+    ChargedBack(u32),
+    /// The chargeback was reversed
+    Reversed(u32),
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 /// Represents client's account data
-pub(crate) struct Client {
+pub struct Client {
     /// Available balance
-    balance_available: f32,
+    #[serde(with = "rust_decimal::serde::str")]
+    balance_available: Decimal,
     /// Held balance
-    balance_held: f32,
+    #[serde(with = "rust_decimal::serde::str")]
+    balance_held: Decimal,
     /// Total balance
-    balance_total: f32,
+    #[serde(with = "rust_decimal::serde::str")]
+    balance_total: Decimal,
     /// Client's transactions
-    transactions: HashMap<u32, (TransactionEnum, f32)>,
+    #[serde(with = "amounts")]
+    transactions: HashMap<u32, (TransactionEnum, Decimal)>,
+    /// IDs of the transactions in the order they were applied, only tracked
+    /// when the history is capped
+    #[serde(default)]
+    tx_order: VecDeque<u32>,
+    /// Number of transactions evicted from the capped history
+    #[serde(default)]
+    evicted: u64,
     /// List of disputed transactions
+    #[serde(with = "tx_id_set")]
     disputed_tx: SetU32,
-    /// Previous transaction ID
+    /// Withdrawals declined for insufficient funds, they were never applied
+    #[serde(default, with = "tx_id_set")]
+    declined: SetU32,
+    /// IDs of the declined withdrawals in the order they were declined, only
+    /// tracked when the history is capped
+    #[serde(default)]
+    declined_order: VecDeque<u32>,
+    /// Funds missing from disputed transactions that were only partially held
+    #[serde(with = "amounts")]
+    shortfalls: HashMap<u32, Decimal>,
+    /// ID of the most recent deposit or withdrawal
     previous_tx_id: u32,
+    /// Number of chargebacks applied
+    chargebacks: u32,
+    /// Charged back transactions and the value each one withdrew from the
+    /// held funds
+    #[serde(with = "amounts")]
+    charged_back: HashMap<u32, Decimal>,
     /// Flag indicating if account is frozen (chargeback)
     frozen: bool,
+    /// Applied dispute events in chronological order
+    #[serde(default)]
+    dispute_history: Vec<DisputeEvent>,
+    /// Group the client is tagged into
+    #[serde(default)]
+    group_id: Option<u32>,
+    /// Number of transactions applied
+    #[serde(default)]
+    applied: u64,
+    /// Sequence number of the engine when each deposit and withdrawal of the
+    /// history was applied, only tracked with a dispute window
+    #[serde(default)]
+    sequences: HashMap<u32, u64>,
 }
 
 impl Default for Client {
     fn default() -> Self {
         Self {
-            balance_available: 0.0,
-            balance_held: 0.0,
-            balance_total: 0.0,
+            balance_available: Decimal::ZERO,
+            balance_held: Decimal::ZERO,
+            balance_total: Decimal::ZERO,
             transactions: HashMap::new(),
+            tx_order: VecDeque::new(),
+            evicted: 0,
             disputed_tx: SetU32::new(),
+            declined: SetU32::new(),
+            declined_order: VecDeque::new(),
+            shortfalls: HashMap::new(),
             previous_tx_id: 0,
+            chargebacks: 0,
+            charged_back: HashMap::new(),
             frozen: false,
+            dispute_history: vec![],
+            group_id: None,
+            applied: 0,
+            sequences: HashMap::new(),
         }
     }
 }
 
 impl Client {
-    /// Returns a new client
-    pub(crate) fn new(tx_id: u32, tx_type: TransactionEnum, tx_amount: f32) -> Self {
-        let balance = {
-            match tx_type {
-                TransactionEnum::Deposit => tx_amount,
-                _ => 0.0,
-            }
-        };
-
-        let mut client = Client {
-            balance_available: balance,
-            balance_total: balance,
-            previous_tx_id: tx_id,
-            ..Default::default()
-        };
-
-        client.chain_tx(tx_id, tx_type, tx_amount);
-        return client;
-    }
-
     /// Checks if the account is currently frozen.
     ///
     /// Returns `true` if it's frozen
@@ -68,18 +119,130 @@ impl Client {
                 tx_id
             ))
         } else {
-            return Ok(self.frozen);
+            Ok(self.frozen)
         }
     }
 
+    /// Returns the ID of the most recent deposit or withdrawal
+    pub(crate) fn last_tx_id(&self) -> u32 {
+        self.previous_tx_id
+    }
+
+    /// Returns `true` if a deposit or withdrawal was applied
+    pub(crate) fn has_transactions(&self) -> bool {
+        !self.transactions.is_empty()
+    }
+
+    /// Returns the available balance
+    pub fn available(&self) -> Decimal {
+        self.balance_available
+    }
+
+    /// Returns the held balance
+    pub fn held(&self) -> Decimal {
+        self.balance_held
+    }
+
+    /// Returns the total balance
+    pub fn total(&self) -> Decimal {
+        self.balance_total
+    }
+
+    /// Returns `true` if the account is frozen (chargeback)
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Returns the applied disputes, resolves, chargebacks and reversals in
+    /// chronological order
+    pub fn dispute_history(&self) -> &[DisputeEvent] {
+        &self.dispute_history
+    }
+
+    /// Returns the disputed transactions holding funds and the value each one
+    /// holds, ordered by transaction ID. The values sum up to the held balance
+    pub fn held_breakdown(&self) -> Vec<(u32, Decimal)> {
+        let mut breakdown: Vec<_> = self
+            .disputed_tx
+            .iter()
+            .filter_map(|tx_id| Some((tx_id, self.get_held_val(tx_id).ok()?)))
+            .collect();
+        breakdown.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        breakdown
+    }
+
+    /// Returns the group the client is tagged into, `None` if it's untagged
+    pub fn group(&self) -> Option<u32> {
+        self.group_id
+    }
+
+    /// Tags the client into a group, replacing the previous one
+    pub(crate) fn set_group(&mut self, group_id: u32) {
+        self.group_id = Some(group_id);
+    }
+
+    /// Records the sequence number of the engine at which a deposit or
+    /// withdrawal of the history was applied
+    pub(crate) fn set_sequence(&mut self, tx_id: u32, sequence: u64) {
+        if self.transactions.contains_key(&tx_id) {
+            self.sequences.insert(tx_id, sequence);
+        }
+    }
+
+    /// Returns the sequence number of the engine at which a deposit or
+    /// withdrawal was applied, `None` if it wasn't recorded
+    pub(crate) fn sequence(&self, tx_id: u32) -> Option<u64> {
+        self.sequences.get(&tx_id).copied()
+    }
+
     /// Store current transaction and chain it to the previous one
-    pub(crate) fn chain_tx(&mut self, tx_id: u32, tx_type: TransactionEnum, tx_amount: f32) {
+    pub(crate) fn chain_tx(
+        &mut self,
+        tx_id: u32,
+        tx_type: TransactionEnum,
+        tx_amount: Decimal,
+        policy: &Policy,
+    ) {
         self.previous_tx_id = tx_id;
         self.transactions.insert(tx_id, (tx_type, tx_amount));
+        if let Some(max_tx) = policy.max_tx_per_client {
+            self.tx_order.push_back(tx_id);
+            if self.transactions.len() > max_tx {
+                self.evict_tx();
+            }
+        }
+    }
+
+    /// Remembers a withdrawal declined for insufficient funds, the oldest one is
+    /// forgotten once the capped history is exceeded
+    fn decline(&mut self, tx_id: u32, policy: &Policy) {
+        self.declined.insert(tx_id);
+        if let Some(max_tx) = policy.max_tx_per_client {
+            self.declined_order.push_back(tx_id);
+            if self.declined_order.len() > max_tx {
+                if let Some(tx_id) = self.declined_order.pop_front() {
+                    self.declined.remove(tx_id);
+                    self.evicted += 1;
+                }
+            }
+        }
+    }
+
+    /// Removes the oldest transaction that isn't disputed or charged back from
+    /// the history, it can't be disputed anymore
+    fn evict_tx(&mut self) {
+        let evictable = self.tx_order.iter().position(|tx_id| {
+            !self.disputed_tx.contains(*tx_id) && !self.charged_back.contains_key(tx_id)
+        });
+        if let Some(tx_id) = evictable.and_then(|index| self.tx_order.remove(index)) {
+            self.transactions.remove(&tx_id);
+            self.sequences.remove(&tx_id);
+            self.evicted += 1;
+        }
     }
 
     /// Checks if there is sufficient funds available to process transaction
-    pub(crate) fn sufficient_funds(&self, tx_amount: f32) -> Result<()> {
+    pub(crate) fn sufficient_funds(&self, tx_amount: Decimal) -> Result<()> {
         if self.balance_available >= tx_amount {
             return Ok(());
         }
@@ -93,68 +256,194 @@ impl Client {
 
     /// Checks the disputed status of a past transaction, and compare
     /// it to the value passed into the call
-    pub(crate) fn disputed_status(&self, tx_id: u32, status: bool) -> Result<()> {
+    ///
+    /// `tx_type` is the operation requiring the status, used to report the error
+    pub(crate) fn disputed_status(
+        &self,
+        tx_id: u32,
+        tx_type: TransactionEnum,
+        status: bool,
+    ) -> Result<()> {
         if self.disputed_tx.contains(tx_id) == status {
             return Ok(());
         }
+        if status {
+            bail!("cannot {tx_type} tx {tx_id}: not under dispute");
+        }
         bail!("Transaction ID: {tx_id} is already labeled as disputed!");
     }
 
     /// Search the logs for the given transaction ID and if found return value of it
     ///
     /// Only transaction of type `Deposit` and `Withdrawal` have values others don't
-    pub fn get_tx_val(&self, tx_id: u32) -> Result<f32> {
+    pub(crate) fn get_tx_val(&self, tx_id: u32) -> Result<Decimal> {
         match self.transactions.get(&tx_id) {
-            Some((_, tx_amount)) => return Ok(tx_amount.to_owned()),
+            Some((_, tx_amount)) => Ok(tx_amount.to_owned()),
             None => bail!("Failed to get value! Transaction ID: {tx_id} does not exist!"),
         }
     }
 
-    /// Processes the current transaction based on it's type
+    /// Returns the value held for a disputed transaction, a partially held
+    /// dispute holds it's value minus the shortfall
+    pub(crate) fn get_held_val(&self, tx_id: u32) -> Result<Decimal> {
+        let shortfall = self.shortfalls.get(&tx_id).copied().unwrap_or_default();
+        Ok(self.get_tx_val(tx_id)? - shortfall)
+    }
+
+    /// Checks that the amount carried by a dispute matches the value recorded
+    /// for the referenced transaction.
+    ///
+    /// Disputes don't carry an amount, so a missing (zero) amount or an unknown
+    /// transaction ID is always considered consistent.
+    pub(crate) fn disputed_amount_matches(&self, tx_id: u32, tx_amount: Decimal) -> bool {
+        tx_amount.is_zero()
+            || self
+                .get_tx_val(tx_id)
+                .map_or(true, |disputed_val| disputed_val == tx_amount)
+    }
+
+    /// Processes the current transaction based on it's type, counting it once
+    /// it's applied
     pub(crate) fn process_tx(
         &mut self,
         tx_id: u32,
         tx_type: TransactionEnum,
-        tx_amount: f32,
+        tx_amount: Decimal,
+        policy: &Policy,
     ) -> Result<()> {
-        self.account_frozen(tx_id)?;
+        self.apply_tx(tx_id, tx_type, tx_amount, policy)?;
+        self.applied += 1;
+        Ok(())
+    }
+
+    /// Applies the current transaction based on it's type
+    fn apply_tx(
+        &mut self,
+        tx_id: u32,
+        tx_type: TransactionEnum,
+        tx_amount: Decimal,
+        policy: &Policy,
+    ) -> Result<()> {
+        // a chargeback is what freezes the account, a repeated one must be reported
+        // as not disputed instead of as a frozen account. It's rejected rather
+        // than ignored so it counts towards `--max-errors` and the exit code, a
+        // file replaying its chargebacks is as suspect as any other bad record
+        if tx_type == TransactionEnum::Chargeback {
+            self.disputed_status(tx_id, tx_type, true)?;
+        }
+        // a reversal is what may unfreeze the account, deposits may be allowed
+        // by the policy
+        let frozen_deposit = tx_type == TransactionEnum::Deposit && policy.allow_frozen_deposits;
+        if tx_type != TransactionEnum::Reversal && !frozen_deposit {
+            self.account_frozen(tx_id)?;
+        }
 
         match tx_type {
+            // a negative deposit is a manual correction, it isn't recorded so
+            // it can't be disputed
+            TransactionEnum::Deposit if tx_amount < Decimal::ZERO => {
+                if !policy.correction_deposits {
+                    bail!("Deposit of transaction ID: {tx_id} has a negative amount!");
+                }
+                let correction = -tx_amount;
+                self.sufficient_funds(correction)?;
+                log::info!(
+                    "Correction deposit of transaction ID: {tx_id} subtracts {correction} from the available balance"
+                );
+                self.balance_available -= correction;
+                self.balance_total = self.balance_available + self.balance_held;
+            }
             // increase balance on a client a account
             TransactionEnum::Deposit => {
                 self.balance_available += tx_amount;
                 self.balance_total = self.balance_available + self.balance_held;
-                self.chain_tx(tx_id, tx_type, tx_amount);
+                self.chain_tx(tx_id, tx_type, tx_amount, policy);
             }
             // If client does not have suffecient funds available, the withdraw will fail
             // and the account's state will remain unchanged.
             TransactionEnum::Withdrawal => {
-                self.sufficient_funds(tx_amount)?;
+                // a later dispute must tell it was never applied
+                if let Err(err) = self.sufficient_funds(tx_amount) {
+                    self.decline(tx_id, policy);
+                    return Err(err);
+                }
+                // amounts are exact decimals, the comparison has no tolerance
+                if policy.deny_zero_balance && self.balance_available == tx_amount {
+                    bail!(
+                        "Withdrawal of transaction ID: {tx_id} would leave no available balance!"
+                    );
+                }
                 self.balance_available -= tx_amount;
                 self.balance_total = self.balance_available + self.balance_held;
-                self.chain_tx(tx_id, tx_type, tx_amount);
+                self.chain_tx(tx_id, tx_type, tx_amount, policy);
             }
             // If the transaction ID is valid, held funds will increase and
             // available balance will decrease by the funds asscociated to the
             // provided transaction ID.
+            // With partial holds a dispute exceeding the available funds holds
+            // only what's available and records the shortfall.
             TransactionEnum::Dispute => {
-                self.disputed_status(tx_id, false)?;
-                let disputed_val = self.get_tx_val(tx_id)?;
-                self.sufficient_funds(disputed_val)?;
-                self.balance_available -= disputed_val;
-                self.balance_held += disputed_val;
+                self.disputed_status(tx_id, tx_type, false)?;
+                // internal-consistency probe, a replayed or corrupted input may
+                // reference a transaction whose recorded value has drifted
+                if cfg!(debug_assertions) && !self.disputed_amount_matches(tx_id, tx_amount) {
+                    log::warn!(
+                        "Dispute amount {tx_amount} does not match the recorded value of transaction ID: {tx_id}!"
+                    );
+                }
+                // e.g. the first record of a client disputing a deposit it never made
+                let Ok(disputed_val) = self.get_tx_val(tx_id) else {
+                    if self.declined.contains(tx_id) {
+                        bail!("cannot dispute tx {tx_id}: the withdrawal was declined for insufficient funds and never applied");
+                    }
+                    if self.evicted > 0 {
+                        bail!("cannot dispute tx {tx_id}: no such transaction, it may have been evicted from the capped history");
+                    }
+                    bail!("cannot dispute tx {tx_id}: no such transaction");
+                };
+                if self.is_withdrawal(tx_id) {
+                    match policy.withdrawal_disputes {
+                        WithdrawalDispute::Reject => {
+                            bail!("cannot dispute tx {tx_id}: withdrawals can't be disputed")
+                        }
+                        // the withdrawn funds may come back, they are held on top
+                        // of the balance so no funds are needed
+                        WithdrawalDispute::Credit => {
+                            self.move_funds(self.movement(tx_id, tx_type, disputed_val, policy));
+                            self.disputed_tx.insert(tx_id);
+                            self.dispute_history.push(DisputeEvent::Opened(tx_id));
+                            return self.checked(tx_id);
+                        }
+                        WithdrawalDispute::Hold => {}
+                    }
+                }
+                let held_val = if policy.partial_hold && disputed_val > self.balance_available {
+                    let held_val = self.balance_available.max(Decimal::ZERO);
+                    let shortfall = disputed_val - held_val;
+                    log::warn!(
+                        "Transaction ID: {tx_id} is partially held, shortfall of {shortfall:.4}!"
+                    );
+                    self.shortfalls.insert(tx_id, shortfall);
+                    held_val
+                } else {
+                    self.sufficient_funds(disputed_val)?;
+                    disputed_val
+                };
+                self.move_funds(self.movement(tx_id, tx_type, held_val, policy));
                 self.disputed_tx.insert(tx_id);
+                self.dispute_history.push(DisputeEvent::Opened(tx_id));
             }
             // If the transaction ID is valid and it is under dispute, held
             // funds will decrease and available balance will increase by the
             // funds asscociated to the provided transaction ID.
             TransactionEnum::Resolve => {
-                self.disputed_status(tx_id, true)?;
-                let disputed_val = self.get_tx_val(tx_id)?;
+                self.disputed_status(tx_id, tx_type, true)?;
+                let disputed_val = self.get_held_val(tx_id)?;
                 if disputed_val <= self.balance_held {
-                    self.balance_available += disputed_val;
-                    self.balance_held -= disputed_val;
+                    self.move_funds(self.movement(tx_id, tx_type, disputed_val, policy));
                     self.disputed_tx.remove(tx_id);
+                    self.shortfalls.remove(&tx_id);
+                    self.dispute_history.push(DisputeEvent::Resolved(tx_id));
                 }
             }
             // If the transaction ID is valid and it is under dispute, funds
@@ -162,51 +451,1000 @@ impl Client {
             // Held funds and total funds will decrease by the funds previously
             // disputed.
             TransactionEnum::Chargeback => {
-                self.disputed_status(tx_id, true)?;
-                let disputed_val = self.get_tx_val(tx_id)?;
+                let disputed_val = self.get_held_val(tx_id)?;
                 if disputed_val <= self.balance_held {
-                    self.frozen = true;
-                    self.balance_held -= disputed_val;
-                    self.balance_total -= disputed_val;
+                    // the account is frozen once enough chargebacks were applied
+                    self.chargebacks += 1;
+                    if self.chargebacks >= policy.freeze_after {
+                        self.frozen = true;
+                    }
+                    self.move_funds(self.movement(tx_id, tx_type, disputed_val, policy));
                     self.disputed_tx.remove(tx_id);
+                    self.shortfalls.remove(&tx_id);
+                    self.charged_back.insert(tx_id, disputed_val);
+                    self.dispute_history.push(DisputeEvent::ChargedBack(tx_id));
                 }
             }
+            // If the transaction ID was charged back, the funds it withdrew are
+            // restored and the account is unfrozen once no other chargeback
+            // keeps it frozen.
+            TransactionEnum::Reversal => {
+                let Some(charged_back_val) = self.charged_back.get(&tx_id).copied() else {
+                    bail!("cannot reverse tx {tx_id}: not charged back");
+                };
+                // the credited withdrawal stands again
+                if self.credits_withdrawal(tx_id, policy) {
+                    self.sufficient_funds(charged_back_val)?;
+                }
+                self.move_funds(self.movement(tx_id, tx_type, charged_back_val, policy));
+                self.charged_back.remove(&tx_id);
+                self.chargebacks -= 1;
+                self.frozen = self.chargebacks >= policy.freeze_after;
+                self.dispute_history.push(DisputeEvent::Reversed(tx_id));
+            }
+            // a transfer involves two accounts, the engine applies it as a
+            // withdrawal and a deposit
+            TransactionEnum::Transfer => {
+                bail!("cannot apply transfer tx {tx_id} to a single account")
+            }
+            // the engine removes the ended client
+            TransactionEnum::EndClient => {
+                bail!("cannot apply end_client tx {tx_id} to an account")
+            }
+        }
+        self.checked(tx_id)
+    }
+
+    /// Returns the change of the total balance a dispute, resolve, chargeback or
+    /// reversal makes once applied, it's derived from the referenced transaction
+    /// before `process_tx` to reconcile the run
+    pub(crate) fn total_change(
+        &self,
+        tx_id: u32,
+        tx_type: TransactionEnum,
+        policy: &Policy,
+    ) -> Decimal {
+        let amount = match tx_type {
+            TransactionEnum::Dispute => self.get_tx_val(tx_id).unwrap_or_default(),
+            TransactionEnum::Resolve | TransactionEnum::Chargeback => {
+                self.get_held_val(tx_id).unwrap_or_default()
+            }
+            TransactionEnum::Reversal => self.charged_back.get(&tx_id).copied().unwrap_or_default(),
+            _ => Decimal::ZERO,
+        };
+        let (available, held) = self.movement(tx_id, tx_type, amount, policy);
+        available + held
+    }
+
+    /// Returns the changes of the available and held balances when a dispute,
+    /// resolve, chargeback or reversal of `tx_id` moves `amount`, the total
+    /// balance changes by their sum
+    fn movement(
+        &self,
+        tx_id: u32,
+        tx_type: TransactionEnum,
+        amount: Decimal,
+        policy: &Policy,
+    ) -> (Decimal, Decimal) {
+        let credited = self.credits_withdrawal(tx_id, policy);
+        match (tx_type, credited) {
+            // the withdrawn funds may come back, they are held on top of the balance
+            (TransactionEnum::Dispute, true) => (Decimal::ZERO, amount),
+            (TransactionEnum::Dispute, false) => (-amount, amount),
+            // a credited withdrawal stands, the funds held for it are dropped
+            (TransactionEnum::Resolve, true) => (Decimal::ZERO, -amount),
+            (TransactionEnum::Resolve, false) => (amount, -amount),
+            // a credited withdrawal is reversed, the client gets it's funds back
+            (TransactionEnum::Chargeback, true) => (amount, -amount),
+            (TransactionEnum::Chargeback, false) => (Decimal::ZERO, -amount),
+            // the credited withdrawal stands again
+            (TransactionEnum::Reversal, true) => (-amount, Decimal::ZERO),
+            (TransactionEnum::Reversal, false) => (amount, Decimal::ZERO),
+            _ => (Decimal::ZERO, Decimal::ZERO),
+        }
+    }
+
+    /// Applies the changes of the available and held balances returned by
+    /// `movement`
+    fn move_funds(&mut self, (available, held): (Decimal, Decimal)) {
+        self.balance_available += available;
+        self.balance_held += held;
+        self.balance_total += available + held;
+    }
+
+    /// Asserts the invariants after a transaction was applied.
+    ///
+    /// A negative held balance means the account is corrupted, it's reported
+    /// as an error in every build
+    fn checked(&self, tx_id: u32) -> Result<()> {
+        if self.balance_held < Decimal::ZERO {
+            log::error!(
+                "Held balance is negative after transaction ID: {tx_id}, the account is corrupted!"
+            );
+            bail!(
+                "held balance went negative after tx {tx_id}: {}",
+                self.balance_held
+            );
+        }
+        debug_assert!(
+            self.invariants_hold(),
+            "Disputed transactions don't match the held balance after transaction ID: {tx_id}!"
+        );
+        Ok(())
+    }
+
+    /// Checks if the given transaction ID is a withdrawal
+    fn is_withdrawal(&self, tx_id: u32) -> bool {
+        matches!(
+            self.transactions.get(&tx_id),
+            Some((TransactionEnum::Withdrawal, _))
+        )
+    }
+
+    /// Checks if the dispute of the given transaction ID credited a withdrawal
+    fn credits_withdrawal(&self, tx_id: u32, policy: &Policy) -> bool {
+        policy.withdrawal_disputes == WithdrawalDispute::Credit && self.is_withdrawal(tx_id)
+    }
+
+    /// Checks that the disputed transactions agree with the held balance,
+    /// every disputed transaction must be logged and the sum of their held values
+    /// must equal the held balance.
+    ///
+    /// Only asserted in debug builds
+    pub(crate) fn invariants_hold(&self) -> bool {
+        let mut disputed_sum = Decimal::ZERO;
+        for tx_id in self.disputed_tx.iter() {
+            match self.get_held_val(tx_id) {
+                Ok(held_val) => disputed_sum += held_val,
+                Err(_) => return false,
+            }
+        }
+        disputed_sum == self.balance_held
+    }
+
+    /// Clears the frozen flag of the account, depending on the policy the held
+    /// funds stay held under their open disputes or are released to the
+    /// available balance.
+    ///
+    /// This is an administrative operation, the input transactions can't unfreeze
+    /// an account
+    pub(crate) fn unfreeze(&mut self, policy: &Policy) -> Result<()> {
+        if !self.frozen {
+            bail!("Account is not frozen!");
+        }
+        self.frozen = false;
+        if policy.release_held_on_unfreeze {
+            self.balance_available += self.balance_held;
+            self.balance_held = Decimal::ZERO;
+            self.disputed_tx = SetU32::new();
+            self.shortfalls.clear();
         }
         Ok(())
     }
 
-    /// Retrieves client's account infomation
-    pub(crate) fn get_info(&self, client_id: &u16) -> Vec<String> {
-        vec![
-            client_id.to_string(),
-            format!("{:.4}", self.balance_available),
-            format!("{:.4}", self.balance_held),
-            format!("{:.4}", self.balance_total),
-            self.frozen.to_string(),
-        ]
+    /// Returns a snapshot of the client's account
+    pub(crate) fn snapshot(&self, client_id: u16) -> Account {
+        Account {
+            client: client_id,
+            available: self.balance_available,
+            held: self.balance_held,
+            total: self.balance_total,
+            locked: self.frozen,
+            last_tx_id: self.previous_tx_id,
+            transactions: self.applied,
+        }
+    }
+}
+
+/// Saves a set of transaction IDs, e.g. the disputed ones, as a sequence
+mod tx_id_set {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use tinyset::SetU32;
+
+    pub(super) fn serialize<S: Serializer>(set: &SetU32, serializer: S) -> Result<S::Ok, S::Error> {
+        set.iter().collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<SetU32, D::Error> {
+        Ok(Vec::<u32>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+/// Saves the amounts of the transactions as strings, the binary checkpoint
+/// can't decode the default representation of a decimal
+mod amounts {
+    use rust_decimal::Decimal;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    use crate::transaction::TransactionEnum;
+
+    /// Decimal saved as a string
+    #[derive(Serialize, Deserialize)]
+    pub(super) struct Amount(#[serde(with = "rust_decimal::serde::str")] Decimal);
+
+    /// Value of the map holding an amount
+    pub(super) trait Value: Copy {
+        type Saved: Serialize + for<'de> Deserialize<'de>;
+        fn save(self) -> Self::Saved;
+        fn load(saved: Self::Saved) -> Self;
+    }
+
+    impl Value for Decimal {
+        type Saved = Amount;
+        fn save(self) -> Amount {
+            Amount(self)
+        }
+        fn load(saved: Amount) -> Self {
+            saved.0
+        }
+    }
+
+    impl Value for (TransactionEnum, Decimal) {
+        type Saved = (TransactionEnum, Amount);
+        fn save(self) -> Self::Saved {
+            (self.0, Amount(self.1))
+        }
+        fn load(saved: Self::Saved) -> Self {
+            (saved.0, saved.1 .0)
+        }
+    }
+
+    pub(super) fn serialize<V: Value, S: Serializer>(
+        map: &HashMap<u32, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(map.iter().map(|(tx_id, value)| (tx_id, value.save())))
+    }
+
+    pub(super) fn deserialize<'de, V: Value, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<u32, V>, D::Error> {
+        Ok(HashMap::<u32, V::Saved>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(tx_id, saved)| (tx_id, V::load(saved)))
+            .collect())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{config::Config, output::Column};
+    use rust_decimal_macros::dec;
+
+    /// Returns a client whose first transaction is a deposit
+    fn deposited(tx_id: u32, tx_amount: Decimal) -> Client {
+        let mut client = Client::default();
+        client
+            .process_tx(
+                tx_id,
+                TransactionEnum::Deposit,
+                tx_amount,
+                &Policy::default(),
+            )
+            .unwrap();
+        client
+    }
 
     #[test]
     pub fn client_creation() {
-        let client1 = Client::new(123456, TransactionEnum::Deposit, 5000.1234);
+        let client1 = deposited(5546465, dec!(5000.1234));
 
-        let mut tx_log: HashMap<u32, (TransactionEnum, f32)> = HashMap::new();
-        tx_log.insert(5546465, (TransactionEnum::Deposit, 5000.1234));
+        let mut tx_log: HashMap<u32, (TransactionEnum, Decimal)> = HashMap::new();
+        tx_log.insert(5546465, (TransactionEnum::Deposit, dec!(5000.1234)));
 
         let client2 = Client {
-            balance_available: 5000.1234,
-            balance_held: 0.0,
-            balance_total: 5000.1234,
+            balance_available: dec!(5000.1234),
+            balance_held: Decimal::ZERO,
+            balance_total: dec!(5000.1234),
             transactions: tx_log,
+            tx_order: VecDeque::new(),
+            evicted: 0,
             disputed_tx: SetU32::new(),
-            previous_tx_id: 123456,
+            declined: SetU32::new(),
+            declined_order: VecDeque::new(),
+            shortfalls: HashMap::new(),
+            previous_tx_id: 5546465,
+            chargebacks: 0,
+            charged_back: HashMap::new(),
             frozen: false,
+            dispute_history: vec![],
+            group_id: None,
+            applied: 1,
+            sequences: HashMap::new(),
         };
         assert_eq!(client1, client2);
     }
+
+    #[test]
+    pub fn disputed_amount_mismatch() {
+        let mut client = deposited(1, dec!(10.0));
+
+        // disputes without an amount and disputes matching the deposit are consistent
+        assert!(client.disputed_amount_matches(1, Decimal::ZERO));
+        assert!(client.disputed_amount_matches(1, dec!(10.0)));
+        assert!(!client.disputed_amount_matches(1, dec!(12.5)));
+
+        // a matching amount is applied as usual
+        client
+            .process_tx(1, TransactionEnum::Dispute, dec!(10.0), &Policy::default())
+            .unwrap();
+        assert_eq!(client.balance_available, Decimal::ZERO);
+        assert_eq!(client.balance_held, dec!(10.0));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    pub fn disputed_amount_mismatch_warned() {
+        let mut client = deposited(1, dec!(10.0));
+        crate::tests::capture_logs();
+        // the dispute is still applied to the recorded value
+        client
+            .process_tx(1, TransactionEnum::Dispute, dec!(12.5), &Policy::default())
+            .unwrap();
+        assert_eq!(client.balance_held, dec!(10.0));
+        assert_eq!(
+            crate::tests::logged(),
+            vec![(
+                log::Level::Warn,
+                "Dispute amount 12.5 does not match the recorded value of transaction ID: 1!"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    pub fn disputed_exact_value() {
+        // a sum a binary float can't represent exactly, 0.1 + 0.1 + 0.1
+        let amount = dec!(0.1) + dec!(0.1) + dec!(0.1);
+        let mut client = deposited(1, amount);
+        assert_eq!(client.get_tx_val(1).unwrap(), dec!(0.3));
+        assert_eq!(client.get_tx_val(1).unwrap().scale(), amount.scale());
+
+        // the stored value is held as is, nothing is parsed again
+        client
+            .process_tx(
+                1,
+                TransactionEnum::Dispute,
+                Decimal::ZERO,
+                &Policy::default(),
+            )
+            .unwrap();
+        assert_eq!(client.balance_held, dec!(0.3));
+        assert_eq!(client.balance_available, Decimal::ZERO);
+        client
+            .process_tx(
+                1,
+                TransactionEnum::Resolve,
+                Decimal::ZERO,
+                &Policy::default(),
+            )
+            .unwrap();
+        assert_eq!(client.balance_available, dec!(0.3));
+        assert_eq!(client.balance_held, Decimal::ZERO);
+    }
+
+    #[test]
+    pub fn chargeback_empties_held() {
+        let policy = Policy::default();
+        let mut client = deposited(1, dec!(12.3456));
+        client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+        assert_eq!(client.balance_held, dec!(12.3456));
+
+        // the charged back value equals the held balance, nothing is left
+        client
+            .process_tx(1, TransactionEnum::Chargeback, Decimal::ZERO, &policy)
+            .unwrap();
+        assert_eq!(client.balance_held, Decimal::ZERO);
+        assert_eq!(
+            client
+                .snapshot(1)
+                .get_info(&Config::default(), &Column::DEFAULT)[2],
+            "0.0000"
+        );
+        assert_eq!(
+            (client.balance_available, client.balance_total),
+            (Decimal::ZERO, Decimal::ZERO)
+        );
+        assert!(client.is_frozen());
+
+        // the frozen account rejects everything but the reversal
+        let frozen = client.clone();
+        for (tx_id, tx_type, tx_amount) in [
+            (2, TransactionEnum::Deposit, dec!(1.0)),
+            (3, TransactionEnum::Withdrawal, dec!(1.0)),
+            (1, TransactionEnum::Dispute, Decimal::ZERO),
+            (1, TransactionEnum::Resolve, Decimal::ZERO),
+        ] {
+            assert!(client
+                .process_tx(tx_id, tx_type, tx_amount, &policy)
+                .is_err());
+        }
+        assert_eq!(client, frozen);
+        client
+            .process_tx(1, TransactionEnum::Reversal, Decimal::ZERO, &policy)
+            .unwrap();
+        assert_eq!(client.balance_available, dec!(12.3456));
+        assert!(!client.is_frozen());
+    }
+
+    #[test]
+    pub fn withdrawal_to_zero() {
+        let withdraw = |policy: &Policy, tx_amount| {
+            let mut client = deposited(1, dec!(0.3));
+            client
+                .process_tx(2, TransactionEnum::Withdrawal, tx_amount, policy)
+                .map(|()| client.balance_available)
+        };
+
+        // the exact remaining balance can be withdrawn, not a fraction more
+        let policy = Policy::default();
+        assert_eq!(
+            withdraw(&policy, dec!(0.1) + dec!(0.2)).unwrap(),
+            Decimal::ZERO
+        );
+        assert!(withdraw(&policy, dec!(0.3001)).is_err());
+
+        // a positive balance must remain
+        let policy = Policy {
+            deny_zero_balance: true,
+            ..Default::default()
+        };
+        let err = withdraw(&policy, dec!(0.3)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Withdrawal of transaction ID: 2 would leave no available balance!"
+        );
+        assert_eq!(withdraw(&policy, dec!(0.2999)).unwrap(), dec!(0.0001));
+    }
+
+    #[test]
+    pub fn correction_deposit() {
+        let policy = Policy {
+            correction_deposits: true,
+            ..Default::default()
+        };
+        let mut client = deposited(1, dec!(10.0));
+        client
+            .process_tx(2, TransactionEnum::Deposit, dec!(-2.5), &policy)
+            .unwrap();
+        assert_eq!(
+            (client.balance_available, client.balance_total),
+            (dec!(7.5), dec!(7.5))
+        );
+        // the correction isn't recorded so it can't be disputed
+        assert!(client
+            .process_tx(2, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .is_err());
+        assert!(client
+            .process_tx(3, TransactionEnum::Deposit, dec!(-8.0), &policy)
+            .is_err());
+
+        // by default negative deposits are rejected
+        let err = client
+            .process_tx(4, TransactionEnum::Deposit, dec!(-1.0), &Policy::default())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Deposit of transaction ID: 4 has a negative amount!"
+        );
+        assert_eq!(client.balance_available, dec!(7.5));
+    }
+
+    #[test]
+    pub fn declined_withdrawal_dispute() {
+        let policy = Policy::default();
+        let mut client = deposited(1, dec!(5.0));
+        assert!(client
+            .process_tx(2, TransactionEnum::Withdrawal, dec!(6.0), &policy)
+            .is_err());
+        // the declined withdrawal isn't recorded
+        assert!(client.get_tx_val(2).is_err());
+
+        let err = client
+            .process_tx(2, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot dispute tx 2: the withdrawal was declined for insufficient funds and never applied"
+        );
+        assert_eq!(
+            client
+                .process_tx(3, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+                .unwrap_err()
+                .to_string(),
+            "cannot dispute tx 3: no such transaction"
+        );
+        assert_eq!(client.balance_available, dec!(5.0));
+    }
+
+    #[test]
+    pub fn duplicate_chargeback() {
+        let mut client = deposited(1, dec!(10.0));
+        client
+            .process_tx(2, TransactionEnum::Deposit, dec!(5.0), &Policy::default())
+            .unwrap();
+        client
+            .process_tx(
+                1,
+                TransactionEnum::Dispute,
+                Decimal::ZERO,
+                &Policy::default(),
+            )
+            .unwrap();
+        client
+            .process_tx(
+                1,
+                TransactionEnum::Chargeback,
+                Decimal::ZERO,
+                &Policy::default(),
+            )
+            .unwrap();
+        let charged_back = client.clone();
+
+        let err = client
+            .process_tx(
+                1,
+                TransactionEnum::Chargeback,
+                Decimal::ZERO,
+                &Policy::default(),
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cannot chargeback tx 1: not under dispute");
+        // the second chargeback leaves the account untouched
+        assert_eq!(client, charged_back);
+    }
+
+    #[test]
+    pub fn disputed_invariants() {
+        let mut client = deposited(1, dec!(10.0));
+        let steps = [
+            (2, TransactionEnum::Deposit, dec!(20.5)),
+            (1, TransactionEnum::Dispute, Decimal::ZERO),
+            (2, TransactionEnum::Dispute, Decimal::ZERO),
+            (3, TransactionEnum::Deposit, dec!(3.25)),
+            (1, TransactionEnum::Resolve, Decimal::ZERO),
+            (2, TransactionEnum::Chargeback, Decimal::ZERO),
+        ];
+        for (tx_id, tx_type, tx_amount) in steps {
+            client
+                .process_tx(tx_id, tx_type, tx_amount, &Policy::default())
+                .unwrap();
+            assert!(client.invariants_hold());
+        }
+
+        // a held balance not backed by a disputed transaction breaks the invariant
+        client.balance_held += dec!(1.0);
+        assert!(!client.invariants_hold());
+        client.balance_held -= dec!(1.0);
+        client.disputed_tx.insert(99);
+        assert!(!client.invariants_hold());
+    }
+
+    #[test]
+    pub fn partial_hold() {
+        let policy = Policy {
+            partial_hold: true,
+            ..Default::default()
+        };
+        let mut client = deposited(1, dec!(10.0));
+        client
+            .process_tx(2, TransactionEnum::Withdrawal, dec!(6.0), &policy)
+            .unwrap();
+
+        // without partial holds the dispute exceeding the available funds is rejected
+        let mut rejecting = client.clone();
+        assert!(rejecting
+            .process_tx(
+                1,
+                TransactionEnum::Dispute,
+                Decimal::ZERO,
+                &Policy::default()
+            )
+            .is_err());
+        assert_eq!(rejecting, client);
+
+        client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+        assert_eq!(client.balance_available, Decimal::ZERO);
+        assert_eq!(client.balance_held, dec!(4.0));
+        assert_eq!(client.balance_total, dec!(4.0));
+        assert_eq!(client.shortfalls.get(&1), Some(&dec!(6.0)));
+
+        // resolving releases only what was held
+        client
+            .process_tx(1, TransactionEnum::Resolve, Decimal::ZERO, &policy)
+            .unwrap();
+        assert_eq!(client.balance_available, dec!(4.0));
+        assert_eq!(client.balance_held, Decimal::ZERO);
+        assert!(client.shortfalls.is_empty());
+    }
+
+    #[test]
+    pub fn last_tx_id() {
+        let policy = Policy::default();
+        let mut client = deposited(1, dec!(10.0));
+        assert_eq!(client.last_tx_id(), 1);
+
+        client
+            .process_tx(2, TransactionEnum::Deposit, dec!(5.0), &policy)
+            .unwrap();
+        client
+            .process_tx(3, TransactionEnum::Withdrawal, dec!(4.0), &policy)
+            .unwrap();
+        assert_eq!(client.last_tx_id(), 3);
+
+        // disputes and declined withdrawals don't create a transaction
+        client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+        assert!(client
+            .process_tx(4, TransactionEnum::Withdrawal, dec!(100.0), &policy)
+            .is_err());
+        assert_eq!(client.last_tx_id(), 3);
+        assert_eq!(client.snapshot(7).last_tx_id, 3);
+    }
+
+    #[test]
+    pub fn getters() {
+        let policy = Policy::default();
+        let mut client = deposited(1, dec!(10.0));
+        client
+            .process_tx(2, TransactionEnum::Deposit, dec!(2.5), &policy)
+            .unwrap();
+        client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+        assert_eq!(
+            (client.available(), client.held(), client.total()),
+            (dec!(2.5), dec!(10), dec!(12.5))
+        );
+        assert!(!client.is_frozen());
+
+        client
+            .process_tx(1, TransactionEnum::Chargeback, Decimal::ZERO, &policy)
+            .unwrap();
+        assert_eq!(client.total(), dec!(2.5));
+        assert!(client.is_frozen());
+    }
+
+    #[test]
+    pub fn first_withdrawal() {
+        let policy = Policy::default();
+        let mut client = Client::default();
+
+        // a client's first transaction is applied like any other one
+        assert!(client
+            .process_tx(1, TransactionEnum::Withdrawal, dec!(5.0), &policy)
+            .is_err());
+        // only the declined withdrawal is remembered, the balances are unchanged
+        assert_eq!(client.snapshot(1), Client::default().snapshot(1));
+        // the declined withdrawal can't be disputed
+        assert!(client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .is_err());
+
+        client
+            .process_tx(2, TransactionEnum::Deposit, dec!(10.0), &policy)
+            .unwrap();
+        client
+            .process_tx(3, TransactionEnum::Withdrawal, dec!(4.0), &policy)
+            .unwrap();
+        assert_eq!(client.available(), dec!(6.0));
+    }
+
+    #[test]
+    pub fn unfreeze() {
+        let mut client = deposited(1, dec!(10.0));
+        let steps = [
+            (2, TransactionEnum::Deposit, dec!(5.0)),
+            (1, TransactionEnum::Dispute, Decimal::ZERO),
+            (2, TransactionEnum::Dispute, Decimal::ZERO),
+            (1, TransactionEnum::Chargeback, Decimal::ZERO),
+        ];
+        for (tx_id, tx_type, tx_amount) in steps {
+            client
+                .process_tx(tx_id, tx_type, tx_amount, &Policy::default())
+                .unwrap();
+        }
+        assert!(client.is_frozen());
+        let frozen = client.clone();
+
+        // by default the open dispute keeps its funds held
+        client.unfreeze(&Policy::default()).unwrap();
+        assert!(!client.is_frozen());
+        assert_eq!(
+            (client.available(), client.held()),
+            (Decimal::ZERO, dec!(5.0))
+        );
+        client
+            .process_tx(
+                2,
+                TransactionEnum::Resolve,
+                Decimal::ZERO,
+                &Policy::default(),
+            )
+            .unwrap();
+        assert_eq!(client.available(), dec!(5.0));
+        assert!(client.unfreeze(&Policy::default()).is_err());
+
+        let policy = Policy {
+            release_held_on_unfreeze: true,
+            ..Default::default()
+        };
+        let mut client = frozen;
+        client.unfreeze(&policy).unwrap();
+        assert_eq!(
+            (client.available(), client.held()),
+            (dec!(5.0), Decimal::ZERO)
+        );
+        assert!(client.invariants_hold());
+        // the released dispute can't be charged back anymore
+        assert!(client
+            .process_tx(2, TransactionEnum::Chargeback, Decimal::ZERO, &policy)
+            .is_err());
+    }
+
+    #[test]
+    pub fn withdrawal_disputes() {
+        let policy = |withdrawal_disputes| Policy {
+            withdrawal_disputes,
+            ..Default::default()
+        };
+        let withdrawn = |available| {
+            let mut client = deposited(1, available);
+            client
+                .process_tx(
+                    2,
+                    TransactionEnum::Withdrawal,
+                    dec!(4.0),
+                    &Policy::default(),
+                )
+                .unwrap();
+            client
+        };
+        let dispute = |client: &mut Client, policy: &Policy| {
+            client.process_tx(2, TransactionEnum::Dispute, Decimal::ZERO, policy)
+        };
+
+        // holding the withdrawn amount needs available funds
+        let hold = policy(WithdrawalDispute::Hold);
+        let mut funded = withdrawn(dec!(10.0));
+        dispute(&mut funded, &hold).unwrap();
+        assert_eq!((funded.available(), funded.held()), (dec!(2.0), dec!(4.0)));
+        let mut unfunded = withdrawn(dec!(5.0));
+        assert!(dispute(&mut unfunded, &hold).is_err());
+
+        let reject = policy(WithdrawalDispute::Reject);
+        let err = dispute(&mut withdrawn(dec!(10.0)), &reject).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot dispute tx 2: withdrawals can't be disputed"
+        );
+
+        // crediting holds the withdrawn amount on top of the balance, even unfunded
+        let credit = policy(WithdrawalDispute::Credit);
+        let mut unfunded = withdrawn(dec!(5.0));
+        dispute(&mut unfunded, &credit).unwrap();
+        assert_eq!(
+            (unfunded.available(), unfunded.held(), unfunded.total()),
+            (dec!(1.0), dec!(4.0), dec!(5.0))
+        );
+        let mut resolved = unfunded.clone();
+        resolved
+            .process_tx(2, TransactionEnum::Resolve, Decimal::ZERO, &credit)
+            .unwrap();
+        assert_eq!(
+            (resolved.available(), resolved.held(), resolved.total()),
+            (dec!(1.0), Decimal::ZERO, dec!(1.0))
+        );
+        unfunded
+            .process_tx(2, TransactionEnum::Chargeback, Decimal::ZERO, &credit)
+            .unwrap();
+        assert_eq!(
+            (unfunded.available(), unfunded.held(), unfunded.total()),
+            (dec!(5.0), Decimal::ZERO, dec!(5.0))
+        );
+        assert!(unfunded.is_frozen());
+    }
+
+    #[test]
+    pub fn freeze_after() {
+        let policy = Policy {
+            freeze_after: 2,
+            ..Default::default()
+        };
+        let mut client = deposited(1, dec!(10.0));
+        let steps = [
+            (2, TransactionEnum::Deposit, dec!(5.0)),
+            (1, TransactionEnum::Dispute, Decimal::ZERO),
+            (1, TransactionEnum::Chargeback, Decimal::ZERO),
+        ];
+        for (tx_id, tx_type, tx_amount) in steps {
+            client
+                .process_tx(tx_id, tx_type, tx_amount, &policy)
+                .unwrap();
+        }
+        // the first chargeback doesn't freeze the account
+        assert!(!client.is_frozen());
+        assert_eq!(client.total(), dec!(5.0));
+
+        client
+            .process_tx(2, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+        client
+            .process_tx(2, TransactionEnum::Chargeback, Decimal::ZERO, &policy)
+            .unwrap();
+        assert!(client.is_frozen());
+    }
+
+    #[test]
+    pub fn reversal() {
+        let policy = Policy::default();
+        let mut client = deposited(1, dec!(10.0));
+        let steps = [
+            (2, TransactionEnum::Deposit, dec!(5.0)),
+            (1, TransactionEnum::Dispute, Decimal::ZERO),
+            (1, TransactionEnum::Chargeback, Decimal::ZERO),
+        ];
+        for (tx_id, tx_type, tx_amount) in steps {
+            client
+                .process_tx(tx_id, tx_type, tx_amount, &policy)
+                .unwrap();
+        }
+        assert!(client.is_frozen());
+        let err = client
+            .process_tx(2, TransactionEnum::Reversal, Decimal::ZERO, &policy)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cannot reverse tx 2: not charged back");
+
+        // the reversal restores the charged back funds and unfreezes the account
+        client
+            .process_tx(1, TransactionEnum::Reversal, Decimal::ZERO, &policy)
+            .unwrap();
+        assert!(!client.is_frozen());
+        assert_eq!(
+            (client.available(), client.held(), client.total()),
+            (dec!(15.0), Decimal::ZERO, dec!(15.0))
+        );
+        assert!(client
+            .process_tx(1, TransactionEnum::Reversal, Decimal::ZERO, &policy)
+            .is_err());
+    }
+
+    #[test]
+    pub fn negative_held() {
+        let policy = Policy::default();
+        let mut client = deposited(1, dec!(10.0));
+        client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+        assert!(client.checked(1).is_ok());
+
+        // what resolve would leave without the held balance guard
+        client.balance_held -= dec!(12.0);
+        let err = client.checked(1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "held balance went negative after tx 1: -2.0"
+        );
+    }
+
+    #[test]
+    pub fn frozen_deposits() {
+        let mut client = deposited(1, dec!(10.0));
+        let steps = [
+            (2, TransactionEnum::Deposit, dec!(5.0)),
+            (1, TransactionEnum::Dispute, Decimal::ZERO),
+            (1, TransactionEnum::Chargeback, Decimal::ZERO),
+        ];
+        for (tx_id, tx_type, tx_amount) in steps {
+            client
+                .process_tx(tx_id, tx_type, tx_amount, &Policy::default())
+                .unwrap();
+        }
+        assert!(client.is_frozen());
+
+        // by default a frozen account rejects deposits
+        let mut rejecting = client.clone();
+        assert!(rejecting
+            .process_tx(3, TransactionEnum::Deposit, dec!(2.0), &Policy::default())
+            .is_err());
+        assert_eq!(rejecting, client);
+
+        let policy = Policy {
+            allow_frozen_deposits: true,
+            ..Default::default()
+        };
+        client
+            .process_tx(3, TransactionEnum::Deposit, dec!(2.0), &policy)
+            .unwrap();
+        assert_eq!((client.available(), client.total()), (dec!(7.0), dec!(7.0)));
+        assert!(client.is_frozen());
+        // withdrawals stay blocked
+        assert!(client
+            .process_tx(4, TransactionEnum::Withdrawal, dec!(1.0), &policy)
+            .is_err());
+    }
+
+    #[test]
+    pub fn orphan_dispute() {
+        let policy = Policy::default();
+        let mut client = Client::default();
+
+        // the client's first record is a dispute of a transaction it never had
+        let err = client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cannot dispute tx 1: no such transaction");
+        assert_eq!(client, Client::default());
+
+        client
+            .process_tx(1, TransactionEnum::Deposit, dec!(3.0), &policy)
+            .unwrap();
+        client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+        assert_eq!(client.held(), dec!(3.0));
+    }
+
+    #[test]
+    pub fn capped_history() {
+        let policy = Policy {
+            max_tx_per_client: Some(3),
+            ..Default::default()
+        };
+        let mut client = Client::default();
+        client
+            .process_tx(1, TransactionEnum::Deposit, dec!(1.0), &policy)
+            .unwrap();
+        client
+            .process_tx(1, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+        for tx_id in 2..=100 {
+            client
+                .process_tx(tx_id, TransactionEnum::Deposit, dec!(1.0), &policy)
+                .unwrap();
+        }
+        assert_eq!(client.transactions.len(), 3);
+        assert_eq!(client.evicted, 97);
+        assert_eq!(client.total(), dec!(100.0));
+
+        // the disputed deposit was kept, the oldest of the others were evicted
+        client
+            .process_tx(1, TransactionEnum::Resolve, Decimal::ZERO, &policy)
+            .unwrap();
+        let err = client
+            .process_tx(50, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot dispute tx 50: no such transaction, it may have been evicted from the capped history"
+        );
+        client
+            .process_tx(100, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap();
+
+        // the declined withdrawals are capped as well
+        for tx_id in 101..=200 {
+            assert!(client
+                .process_tx(tx_id, TransactionEnum::Withdrawal, dec!(1000.0), &policy)
+                .is_err());
+        }
+        assert_eq!(client.declined.len(), 3);
+        let err = client
+            .process_tx(200, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap_err();
+        assert!(err.to_string().contains("the withdrawal was declined"));
+        let err = client
+            .process_tx(101, TransactionEnum::Dispute, Decimal::ZERO, &policy)
+            .unwrap_err();
+        assert!(err.to_string().contains("it may have been evicted"));
+    }
 }
@@ -0,0 +1,966 @@
+use anyhow::{bail, Context, Result};
+use csv::{Reader, ReaderBuilder, Trim};
+use std::{collections::HashSet, ffi::OsString, io::Read, path::PathBuf, time::Duration};
+
+use crate::{
+    account::Account,
+    event::LogFormat,
+    input::InputFormat,
+    output::{Column, OutputFormat},
+    policy::{Policy, Precision, TxOrder, WithdrawalDispute},
+};
+
+/// Number of input records between two checkpoints when it's not configured
+const CHECKPOINT_EVERY: u64 = 10_000;
+
+/// Environment variable holding the input file when no path is given as
+/// argument, e.g. in a container
+const INPUT_VAR: &str = "PAYMENT_ENGINE_INPUT";
+
+/// Largest number of decimal places a decimal amount can have
+const MAX_DECIMAL_PLACES: u32 = 28;
+
+/// Ordering of the accounts in the output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// Ascending client ID
+    #[default]
+    Client,
+    /// Largest total balance first
+    Total,
+    /// Largest held balance first
+    Held,
+}
+
+impl SortBy {
+    /// Sorts the accounts, ties are ordered by client ID
+    pub(crate) fn sort(self, accounts: &mut [Account]) {
+        match self {
+            SortBy::Client => accounts.sort(),
+            SortBy::Total => {
+                accounts.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.client.cmp(&b.client)))
+            }
+            SortBy::Held => {
+                accounts.sort_by(|a, b| b.held.cmp(&a.held).then_with(|| a.client.cmp(&b.client)))
+            }
+        }
+    }
+}
+
+/// Options of the CSV parser
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputConfig {
+    /// Field delimiter
+    delimiter: u8,
+    /// Allow records with a different number of fields
+    flexible: bool,
+    /// Whitespace trimming applied to headers and fields
+    trim: Trim,
+    /// Treat the first record as the header
+    has_headers: bool,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            // disputes, resolves and chargebacks don't have the amount field
+            flexible: true,
+            trim: Trim::All,
+            has_headers: true,
+        }
+    }
+}
+
+impl InputConfig {
+    /// Returns a builder starting from the default options
+    pub fn builder() -> InputConfigBuilder {
+        InputConfigBuilder::default()
+    }
+
+    /// Returns a CSV parser reading from `rdr` with these options
+    pub fn from_reader<R: Read>(&self, rdr: R) -> Reader<R> {
+        ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .flexible(self.flexible)
+            .trim(self.trim)
+            .has_headers(self.has_headers)
+            .from_reader(rdr)
+    }
+}
+
+/// Builds an [`InputConfig`]
+#[derive(Debug, Default, Clone)]
+pub struct InputConfigBuilder {
+    config: InputConfig,
+}
+
+impl InputConfigBuilder {
+    /// Sets the field delimiter, defaults to `,`
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.config.delimiter = delimiter;
+        self
+    }
+
+    /// Allows records with a different number of fields, defaults to `true`
+    pub fn flexible(mut self, flexible: bool) -> Self {
+        self.config.flexible = flexible;
+        self
+    }
+
+    /// Sets the whitespace trimming, defaults to [`Trim::All`]
+    pub fn trim(mut self, trim: Trim) -> Self {
+        self.config.trim = trim;
+        self
+    }
+
+    /// Treats the first record as the header, defaults to `true`
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.config.has_headers = has_headers;
+        self
+    }
+
+    /// Returns the configured options
+    pub fn build(self) -> InputConfig {
+        self.config
+    }
+}
+
+/// Options read from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Paths of the files to process, their transactions are applied in order
+    pub inputs: Vec<PathBuf>,
+    /// Process every file as an independent ledger with its own output section
+    pub separate: bool,
+    /// Memory-map the input file instead of reading it through a buffer
+    pub mmap: bool,
+    /// Format of the input file
+    pub input_format: InputFormat,
+    /// Derive deposits and withdrawals from the sign of their amount
+    pub signed_amounts: bool,
+    /// Read the amounts as integer minor units
+    pub amounts_in_cents: bool,
+    /// Read the amounts in parentheses as negatives
+    pub accounting_negatives: bool,
+    /// Maximum number of digits of the integer part of the amounts, `None`
+    /// doesn't limit them
+    pub max_integer_digits: Option<u32>,
+    /// Prefix of the input lines skipped as comments
+    pub comment: Option<String>,
+    /// Options of the CSV parser
+    pub csv: InputConfig,
+    /// Ordering of the accounts in the output
+    pub sort_by: SortBy,
+    /// Format of the output
+    pub output_format: OutputFormat,
+    /// Decimal separator of the output amounts
+    pub decimal_separator: char,
+    /// Columns written after the client ID, in order
+    pub columns: Vec<Column>,
+    /// Prefix of the client IDs written, e.g. the name of the source
+    pub client_prefix: String,
+    /// Only apply and output the transactions of these clients, `None` keeps
+    /// every client
+    pub clients: Option<HashSet<u16>>,
+    /// Leave out the transactions and accounts of these clients
+    pub excluded_clients: HashSet<u16>,
+    /// Only output the accounts that are frozen
+    pub locked_only: bool,
+    /// Don't output the accounts without any deposit or withdrawal applied
+    pub hide_inactive: bool,
+    /// Rules applied when processing transactions
+    pub policy: Policy,
+    /// Number of bins of the balance histogram printed to stderr
+    pub histogram: Option<usize>,
+    /// Print to stderr the balances summed across every client
+    pub system_totals: bool,
+    /// Only report statistics about the file structure, no balance is computed
+    pub check: bool,
+    /// Maximum size in bytes of an input line, longer lines abort the processing
+    pub max_record_size: Option<usize>,
+    /// Abort on a malformed record instead of skipping it
+    pub strict: bool,
+    /// Abort the run once this number of transactions were rejected, `None`
+    /// doesn't limit them
+    pub max_errors: Option<u64>,
+    /// Number of records at the start of the input that are not applied
+    pub skip: u64,
+    /// Stop applying transactions at the first one whose ID is greater,
+    /// `None` applies the whole input
+    pub as_of: Option<u32>,
+    /// Print to stderr the balance changes of every transaction
+    pub explain: bool,
+    /// Validate every input record before applying any of them, nothing is
+    /// output unless the whole input is valid
+    pub two_pass: bool,
+    /// File where the balances of the client after every applied transaction
+    /// are written as CSV records
+    pub trace: Option<PathBuf>,
+    /// Format of the rejected transactions and skipped records reports
+    pub log_format: LogFormat,
+    /// File where the state of the run is periodically saved, a killed run
+    /// resumes from it
+    pub checkpoint: Option<PathBuf>,
+    /// Number of input records between two checkpoints
+    pub checkpoint_every: u64,
+    /// Keep reading the lines appended to the input file, the balances are
+    /// written again at this interval while they change
+    pub follow: Option<Duration>,
+    /// Number of tasks applying the transactions, every client is applied by
+    /// the one its ID is assigned to
+    pub workers: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            inputs: vec![],
+            separate: false,
+            mmap: false,
+            input_format: InputFormat::default(),
+            signed_amounts: false,
+            amounts_in_cents: false,
+            max_integer_digits: None,
+            accounting_negatives: false,
+            comment: None,
+            csv: InputConfig::default(),
+            sort_by: SortBy::default(),
+            output_format: OutputFormat::default(),
+            decimal_separator: '.',
+            columns: Column::DEFAULT.to_vec(),
+            client_prefix: String::new(),
+            clients: None,
+            excluded_clients: HashSet::new(),
+            locked_only: false,
+            hide_inactive: false,
+            policy: Policy::default(),
+            histogram: None,
+            system_totals: false,
+            check: false,
+            max_record_size: None,
+            strict: true,
+            max_errors: None,
+            skip: 0,
+            as_of: None,
+            explain: false,
+            two_pass: false,
+            trace: None,
+            log_format: LogFormat::default(),
+            checkpoint: None,
+            checkpoint_every: CHECKPOINT_EVERY,
+            follow: None,
+            workers: 1,
+        }
+    }
+}
+
+impl Config {
+    /// Returns whether the transactions of a client are applied, it's selected
+    /// by `clients` and not excluded
+    pub(crate) fn includes(&self, client_id: u16) -> bool {
+        self.clients
+            .as_ref()
+            .is_none_or(|clients| clients.contains(&client_id))
+            && !self.excluded_clients.contains(&client_id)
+    }
+
+    /// Parses the command line arguments, the program name must be skipped.
+    ///
+    /// Without a path argument the input file is read from the
+    /// `PAYMENT_ENGINE_INPUT` environment variable
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        Self::from_args_env(args, std::env::var_os(INPUT_VAR))
+    }
+
+    /// Parses the command line arguments, `env_input` is the input file used
+    /// when no path is given as argument
+    fn from_args_env<I: IntoIterator<Item = String>>(
+        args: I,
+        env_input: Option<OsString>,
+    ) -> Result<Self> {
+        let mut config = Config::default();
+        // the count is added to the selected columns whatever the options order
+        let mut with_counts = false;
+        let mut summary_only = false;
+        // an explicit precision mode wins over the one implied by the minor
+        // units whatever the options order
+        let mut precision = None;
+        let mut minor_units = false;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--mmap" => config.mmap = true,
+                "--check" => config.check = true,
+                "--explain" => config.explain = true,
+                "--two-pass" => config.two_pass = true,
+                "--system-totals" => config.system_totals = true,
+                "--signed-amounts" => config.signed_amounts = true,
+                "--amounts-in-cents" => config.amounts_in_cents = true,
+                "--accounting-negatives" => config.accounting_negatives = true,
+                "--comment-prefix" => {
+                    let prefix = value(&mut args, &arg)?;
+                    if prefix.is_empty() {
+                        bail!("The comment prefix can't be empty");
+                    }
+                    config.comment = Some(prefix);
+                }
+                "--separate" => config.separate = true,
+                "--no-strict" => config.strict = false,
+                "--locked-only" => config.locked_only = true,
+                "--hide-inactive" => config.hide_inactive = true,
+                "--partial-hold" => config.policy.partial_hold = true,
+                "--unique-tx-ids" => config.policy.unique_tx_ids = true,
+                "--allow-deposits-when-frozen" => config.policy.allow_frozen_deposits = true,
+                "--deny-withdrawal-overdraft-to-zero" => config.policy.deny_zero_balance = true,
+                "--allow-correction-deposits" => config.policy.correction_deposits = true,
+                "--require-account-creation" => config.policy.require_account_creation = true,
+                "--header" => config.csv.has_headers = true,
+                "--no-header" => config.csv.has_headers = false,
+                "--trim" => {
+                    config.csv.trim = match value(&mut args, &arg)?.as_str() {
+                        "all" => Trim::All,
+                        "headers" => Trim::Headers,
+                        "fields" => Trim::Fields,
+                        "none" => Trim::None,
+                        mode => {
+                            bail!("Invalid trim mode {mode}, expected all, headers, fields or none")
+                        }
+                    }
+                }
+                "--input-format" => {
+                    config.input_format = match value(&mut args, &arg)?.as_str() {
+                        "csv" => InputFormat::Csv,
+                        "ndjson" => InputFormat::Ndjson,
+                        format => bail!("Invalid input format {format}, expected csv or ndjson"),
+                    }
+                }
+                "--format" => {
+                    config.output_format = match value(&mut args, &arg)?.as_str() {
+                        "csv" => OutputFormat::Csv,
+                        "bincode" => OutputFormat::Bincode,
+                        format => bail!("Invalid output format {format}, expected csv or bincode"),
+                    }
+                }
+                "--decimal-separator" => {
+                    let separator = value(&mut args, &arg)?;
+                    let mut chars = separator.chars();
+                    config.decimal_separator = match (chars.next(), chars.next()) {
+                        (Some(separator), None) if !separator.is_ascii_digit() => separator,
+                        _ => bail!(
+                            "Invalid decimal separator {separator}, expected a single character"
+                        ),
+                    };
+                }
+                "--log-format" => {
+                    config.log_format = match value(&mut args, &arg)?.as_str() {
+                        "text" => LogFormat::Text,
+                        "json" => LogFormat::Json,
+                        format => bail!("Invalid log format {format}, expected text or json"),
+                    }
+                }
+                "--withdrawal-disputes" => {
+                    config.policy.withdrawal_disputes = match value(&mut args, &arg)?.as_str() {
+                        "hold" => WithdrawalDispute::Hold,
+                        "reject" => WithdrawalDispute::Reject,
+                        "credit" => WithdrawalDispute::Credit,
+                        mode => {
+                            bail!("Invalid withdrawal disputes mode {mode}, expected hold, reject or credit")
+                        }
+                    }
+                }
+                "--precision" => {
+                    precision = Some(match value(&mut args, &arg)?.as_str() {
+                        "round" => Precision::Round,
+                        "reject" => Precision::Reject,
+                        mode => bail!("Invalid precision mode {mode}, expected round or reject"),
+                    })
+                }
+                "--minor-units" => {
+                    let places = value(&mut args, &arg)?;
+                    config.policy.decimal_places = match places.parse() {
+                        Ok(places) if places <= MAX_DECIMAL_PLACES => places,
+                        _ => bail!(
+                            "Invalid minor units {places}, expected at most {MAX_DECIMAL_PLACES} decimal places"
+                        ),
+                    };
+                    minor_units = true;
+                }
+                "--require-ordered" => {
+                    config.policy.ordered_tx_ids = Some(match value(&mut args, &arg)?.as_str() {
+                        "global" => TxOrder::Global,
+                        "client" => TxOrder::PerClient,
+                        order => bail!("Invalid tx order {order}, expected global or client"),
+                    })
+                }
+                "--sort-by" => {
+                    config.sort_by = match value(&mut args, &arg)?.as_str() {
+                        "client" => SortBy::Client,
+                        "total" => SortBy::Total,
+                        "held" => SortBy::Held,
+                        order => {
+                            bail!("Invalid sort order {order}, expected client, total or held")
+                        }
+                    }
+                }
+                "--client-prefix" => config.client_prefix = value(&mut args, &arg)?,
+                "--with-counts" => with_counts = true,
+                "--summary-only" => summary_only = true,
+                "--columns" => {
+                    let columns = value(&mut args, &arg)?;
+                    config.columns = vec![];
+                    for name in columns.split(',') {
+                        let column = Column::ALL
+                            .into_iter()
+                            .find(|column| column.name() == name.trim())
+                            .with_context(|| {
+                                format!("Invalid column {name}, expected available, held, total, locked or num_transactions")
+                            })?;
+                        if config.columns.contains(&column) {
+                            bail!("Column {name} is selected more than once");
+                        }
+                        config.columns.push(column);
+                    }
+                }
+                "--clients" => {
+                    let clients = parse_clients(&value(&mut args, &arg)?)?;
+                    config
+                        .clients
+                        .get_or_insert_with(HashSet::new)
+                        .extend(clients);
+                }
+                "--clients-file" => {
+                    let clients = read_clients(&value(&mut args, &arg)?)?;
+                    config
+                        .clients
+                        .get_or_insert_with(HashSet::new)
+                        .extend(clients);
+                }
+                "--exclude-clients-file" => {
+                    let clients = read_clients(&value(&mut args, &arg)?)?;
+                    config.excluded_clients.extend(clients);
+                }
+                "--histogram" => {
+                    let bins = value(&mut args, &arg)?;
+                    config.histogram = Some(
+                        bins.parse()
+                            .with_context(|| format!("Invalid number of bins {bins}"))?,
+                    );
+                }
+                "--skip" => {
+                    let records = value(&mut args, &arg)?;
+                    config.skip = records
+                        .parse()
+                        .with_context(|| format!("Invalid number of records {records}"))?;
+                }
+                "--as-of" => {
+                    let tx_id = value(&mut args, &arg)?;
+                    config.as_of = Some(
+                        tx_id
+                            .parse()
+                            .with_context(|| format!("Invalid transaction ID {tx_id}"))?,
+                    );
+                }
+                "--freeze-after" => {
+                    let chargebacks = value(&mut args, &arg)?;
+                    config.policy.freeze_after = match chargebacks.parse() {
+                        Ok(0) | Err(_) => bail!(
+                            "Invalid number of chargebacks {chargebacks}, expected at least 1"
+                        ),
+                        Ok(chargebacks) => chargebacks,
+                    };
+                }
+                "--max-errors" => {
+                    let errors = value(&mut args, &arg)?;
+                    config.max_errors = match errors.parse() {
+                        Ok(0) | Err(_) => {
+                            bail!("Invalid number of errors {errors}, expected at least 1")
+                        }
+                        Ok(errors) => Some(errors),
+                    };
+                }
+                "--trace" => config.trace = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--checkpoint" => config.checkpoint = Some(PathBuf::from(value(&mut args, &arg)?)),
+                "--checkpoint-every" => {
+                    let records = value(&mut args, &arg)?;
+                    config.checkpoint_every = match records.parse() {
+                        Ok(0) | Err(_) => {
+                            bail!("Invalid number of records {records}, expected at least 1")
+                        }
+                        Ok(records) => records,
+                    };
+                }
+                "--follow" => {
+                    let seconds = value(&mut args, &arg)?;
+                    config.follow = match seconds.parse().map(Duration::try_from_secs_f64) {
+                        Ok(Ok(interval)) if !interval.is_zero() => Some(interval),
+                        _ => bail!(
+                            "Invalid interval {seconds}, expected a positive number of seconds"
+                        ),
+                    };
+                }
+                "--workers" => {
+                    let workers = value(&mut args, &arg)?;
+                    config.workers = match workers.parse() {
+                        Ok(0) | Err(_) => {
+                            bail!("Invalid number of workers {workers}, expected at least 1")
+                        }
+                        Ok(workers) => workers,
+                    };
+                }
+                "--max-clients" => {
+                    let clients = value(&mut args, &arg)?;
+                    config.policy.max_clients = Some(
+                        clients
+                            .parse()
+                            .with_context(|| format!("Invalid number of clients {clients}"))?,
+                    );
+                }
+                "--dispute-window" => {
+                    let transactions = value(&mut args, &arg)?;
+                    config.policy.dispute_window =
+                        Some(transactions.parse().with_context(|| {
+                            format!("Invalid number of transactions {transactions}")
+                        })?);
+                }
+                "--max-tx-per-client" => {
+                    let transactions = value(&mut args, &arg)?;
+                    config.policy.max_tx_per_client = match transactions.parse() {
+                        Ok(0) | Err(_) => bail!(
+                            "Invalid number of transactions {transactions}, expected at least 1"
+                        ),
+                        Ok(transactions) => Some(transactions),
+                    };
+                }
+                "--max-integer-digits" => {
+                    let digits = value(&mut args, &arg)?;
+                    config.max_integer_digits = match digits.parse() {
+                        Ok(0) | Err(_) => {
+                            bail!("Invalid number of digits {digits}, expected at least 1")
+                        }
+                        Ok(digits) => Some(digits),
+                    };
+                }
+                "--max-record-size" => {
+                    let size = value(&mut args, &arg)?;
+                    config.max_record_size = Some(
+                        size.parse()
+                            .with_context(|| format!("Invalid record size {size}"))?,
+                    );
+                }
+                option if option.starts_with("--") => bail!("Unknown option {option}"),
+                _ => config.inputs.push(PathBuf::from(arg)),
+            }
+        }
+
+        // amounts finer than the currency's minor unit are invalid
+        if let Some(precision) = precision.or(minor_units.then_some(Precision::Reject)) {
+            config.policy.precision = precision;
+        }
+        if with_counts && !config.columns.contains(&Column::Transactions) {
+            config.columns.push(Column::Transactions);
+        }
+        // the summary replaces the table, its columns and format don't apply
+        if summary_only {
+            if config.output_format != OutputFormat::Csv || config.columns != Column::DEFAULT {
+                bail!("Option --summary-only can't be combined with --format, --columns or --with-counts");
+            }
+            config.output_format = OutputFormat::Summary;
+        }
+        // the arguments take precedence over the environment
+        if config.inputs.is_empty() {
+            match env_input.filter(|path| !path.is_empty()) {
+                Some(path) => config.inputs.push(PathBuf::from(path)),
+                None => bail!(
+                    "Unable to get arguments, file.csv expected as argument or in {INPUT_VAR}"
+                ),
+            }
+        }
+        // every ledger would resume from the same checkpoint
+        if config.separate && config.checkpoint.is_some() {
+            bail!("Option --separate can't be combined with --checkpoint");
+        }
+        // the trace would only hold the steps of the last ledger or of the
+        // resumed run
+        if config.trace.is_some() && (config.separate || config.checkpoint.is_some()) {
+            bail!("Option --trace can't be combined with --separate or --checkpoint");
+        }
+        // the section lines can't be mixed with the encoded accounts
+        if config.separate && config.output_format == OutputFormat::Bincode {
+            bail!("Option --separate only supports the csv output format");
+        }
+        // a followed file never ends, it can't be validated or resumed
+        if config.follow.is_some() {
+            if config.inputs.len() != 1 {
+                bail!("Option --follow needs a single input file");
+            }
+            // the mapping doesn't grow with the file
+            if config.separate || config.two_pass || config.checkpoint.is_some() || config.mmap {
+                bail!(
+                    "Option --follow can't be combined with --separate, --two-pass, --checkpoint or --mmap"
+                );
+            }
+        }
+        // the state shared by every client can't be split among the workers,
+        // nor the reports of the whole run
+        if config.workers > 1 {
+            let policy = &config.policy;
+            if policy.unique_tx_ids
+                || policy.ordered_tx_ids == Some(TxOrder::Global)
+                || policy.dispute_window.is_some()
+                || policy.max_clients.is_some()
+            {
+                bail!("Option --workers can't be combined with --unique-tx-ids, --require-ordered global, --dispute-window or --max-clients");
+            }
+            if config.separate
+                || config.follow.is_some()
+                || config.checkpoint.is_some()
+                || config.max_errors.is_some()
+                || config.histogram.is_some()
+                || config.system_totals
+            {
+                bail!("Option --workers can't be combined with --separate, --follow, --checkpoint, --max-errors, --histogram or --system-totals");
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Parses comma separated client IDs
+fn parse_clients(clients: &str) -> Result<HashSet<u16>> {
+    clients
+        .split(',')
+        .map(|client| {
+            client
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid client ID {client}"))
+        })
+        .collect()
+}
+
+/// Reads the client IDs of a file, one per line or comma separated. Blank
+/// lines and the ones starting with `#` are ignored
+fn read_clients(path: &str) -> Result<HashSet<u16>> {
+    let clients = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read the client IDs of {path}"))?;
+    let mut ids = HashSet::new();
+    for line in clients.lines().map(str::trim) {
+        if !line.is_empty() && !line.starts_with('#') {
+            ids.extend(
+                parse_clients(line).with_context(|| format!("Invalid client IDs in {path}"))?,
+            );
+        }
+    }
+    Ok(ids)
+}
+
+/// Returns the value following an option
+fn value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String> {
+    args.next()
+        .with_context(|| format!("Missing value for option {option}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, InputConfig, SortBy};
+    use crate::{
+        event::LogFormat,
+        input::InputFormat,
+        output::{Column, OutputFormat},
+        policy::{Policy, Precision, TxOrder, WithdrawalDispute},
+        tests::TempPath,
+    };
+    use csv::{StringRecord, Trim};
+    use std::time::Duration;
+    use std::{collections::HashSet, ffi::OsString, path::PathBuf};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_args() {
+        let config = Config::from_args(args(&[
+            "--mmap",
+            "csv_files/tx.csv",
+            "--trim",
+            "none",
+            "--sort-by",
+            "held",
+            "--partial-hold",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                inputs: vec![PathBuf::from("csv_files/tx.csv")],
+                mmap: true,
+                csv: InputConfig::builder().trim(Trim::None).build(),
+                sort_by: SortBy::Held,
+                policy: Policy {
+                    partial_hold: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        );
+
+        let config = Config::from_args(args(&["--histogram", "5", "tx.csv"])).unwrap();
+        assert_eq!(config.histogram, Some(5));
+        let config = Config::from_args(args(&["--unique-tx-ids", "tx.csv"])).unwrap();
+        assert!(config.policy.unique_tx_ids);
+        let config = Config::from_args(args(&["--allow-deposits-when-frozen", "tx.csv"])).unwrap();
+        assert!(config.policy.allow_frozen_deposits);
+        let config =
+            Config::from_args(args(&["--deny-withdrawal-overdraft-to-zero", "tx.csv"])).unwrap();
+        assert!(config.policy.deny_zero_balance);
+        let config = Config::from_args(args(&["--allow-correction-deposits", "tx.csv"])).unwrap();
+        assert!(config.policy.correction_deposits);
+        let config = Config::from_args(args(&["--require-account-creation", "tx.csv"])).unwrap();
+        assert!(config.policy.require_account_creation);
+        let config = Config::from_args(args(&["--input-format", "ndjson", "tx.csv"])).unwrap();
+        assert_eq!(config.input_format, InputFormat::Ndjson);
+        let config = Config::from_args(args(&["--format", "bincode", "tx.csv"])).unwrap();
+        assert_eq!(config.output_format, OutputFormat::Bincode);
+        let config = Config::from_args(args(&["--decimal-separator", ",", "tx.csv"])).unwrap();
+        assert_eq!(config.decimal_separator, ',');
+        let config = Config::from_args(args(&["--log-format", "json", "tx.csv"])).unwrap();
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert!(Config::from_args(args(&["--decimal-separator", ",,", "tx.csv"])).is_err());
+        assert!(Config::from_args(args(&["--format", "bincode", "--separate", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--summary-only", "tx.csv"])).unwrap();
+        assert_eq!(config.output_format, OutputFormat::Summary);
+        assert!(Config::from_args(args(&["--summary-only", "--with-counts", "tx.csv"])).is_err());
+        assert!(
+            Config::from_args(args(&["--format", "bincode", "--summary-only", "tx.csv"])).is_err()
+        );
+        assert!(
+            Config::from_args(args(&["--check", "tx.csv"]))
+                .unwrap()
+                .check
+        );
+        let config = Config::from_args(args(&["--locked-only", "--no-strict", "tx.csv"])).unwrap();
+        assert!(config.locked_only && !config.strict);
+        assert!(
+            Config::from_args(args(&["--explain", "tx.csv"]))
+                .unwrap()
+                .explain
+        );
+        assert!(
+            Config::from_args(args(&["--system-totals", "tx.csv"]))
+                .unwrap()
+                .system_totals
+        );
+        assert_eq!(
+            Config::from_args(args(&["--comment-prefix", "//", "tx.csv"]))
+                .unwrap()
+                .comment
+                .as_deref(),
+            Some("//")
+        );
+        assert!(Config::from_args(args(&["--comment-prefix", "", "tx.csv"])).is_err());
+        assert!(
+            Config::from_args(args(&["--amounts-in-cents", "tx.csv"]))
+                .unwrap()
+                .amounts_in_cents
+        );
+        assert!(
+            Config::from_args(args(&["--signed-amounts", "tx.csv"]))
+                .unwrap()
+                .signed_amounts
+        );
+        assert!(
+            Config::from_args(args(&["--two-pass", "tx.csv"]))
+                .unwrap()
+                .two_pass
+        );
+        assert!(
+            Config::from_args(args(&["--hide-inactive", "tx.csv"]))
+                .unwrap()
+                .hide_inactive
+        );
+        let config = Config::from_args(args(&["--max-record-size", "1024", "tx.csv"])).unwrap();
+        assert_eq!(config.max_record_size, Some(1024));
+        let config = Config::from_args(args(&[
+            "--checkpoint",
+            "run.ckpt",
+            "--checkpoint-every",
+            "500",
+            "tx.csv",
+        ]))
+        .unwrap();
+        assert_eq!(
+            (config.checkpoint, config.checkpoint_every),
+            (Some(PathBuf::from("run.ckpt")), 500)
+        );
+        assert!(Config::from_args(args(&["--checkpoint-every", "0", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--workers", "4", "tx.csv"])).unwrap();
+        assert_eq!(config.workers, 4);
+        assert!(Config::from_args(args(&["--workers", "0", "tx.csv"])).is_err());
+        assert!(Config::from_args(args(&["--workers", "4", "--unique-tx-ids", "tx.csv"])).is_err());
+        assert!(
+            Config::from_args(args(&["--workers", "4", "--max-errors", "3", "tx.csv"])).is_err()
+        );
+        assert!(Config::from_args(args(&["--workers", "4", "--trace", "t.csv", "tx.csv"])).is_ok());
+        let config = Config::from_args(args(&["--clients", "1,5,9", "tx.csv"])).unwrap();
+        assert_eq!(config.clients, Some(HashSet::from([1, 5, 9])));
+        assert!(Config::from_args(args(&["--clients", "1,x", "tx.csv"])).is_err());
+        let file = TempPath::new("clients.txt");
+        std::fs::write(&file, "# compliance hold\n2\n\n7, 8\n").unwrap();
+        let path = file.display().to_string();
+        let config =
+            Config::from_args(args(&["--clients", "1", "--clients-file", &path, "tx.csv"]))
+                .unwrap();
+        assert_eq!(config.clients, Some(HashSet::from([1, 2, 7, 8])));
+        let config = Config::from_args(args(&["--exclude-clients-file", &path, "tx.csv"])).unwrap();
+        assert_eq!(config.excluded_clients, HashSet::from([2, 7, 8]));
+        assert!(!config.includes(7) && config.includes(1));
+        assert!(Config::from_args(args(&["--clients-file", "missing.txt", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--columns", "locked,available", "tx.csv"])).unwrap();
+        assert_eq!(config.columns, vec![Column::Locked, Column::Available]);
+        assert!(Config::from_args(args(&["--columns", "held,held", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--client-prefix", "eu-", "tx.csv"])).unwrap();
+        assert_eq!(config.client_prefix, "eu-");
+        let config = Config::from_args(args(&["--with-counts", "tx.csv"])).unwrap();
+        assert_eq!(config.columns.last(), Some(&Column::Transactions));
+        let config =
+            Config::from_args(args(&["--with-counts", "--columns", "held", "tx.csv"])).unwrap();
+        assert_eq!(config.columns, vec![Column::Held, Column::Transactions]);
+        assert!(Config::from_args(args(&["--columns", "client", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--max-tx-per-client", "8", "tx.csv"])).unwrap();
+        assert_eq!(config.policy.max_tx_per_client, Some(8));
+        assert!(Config::from_args(args(&["--max-tx-per-client", "0", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--max-clients", "2", "tx.csv"])).unwrap();
+        assert_eq!(config.policy.max_clients, Some(2));
+        let config = Config::from_args(args(&["--dispute-window", "100", "tx.csv"])).unwrap();
+        assert_eq!(config.policy.dispute_window, Some(100));
+        assert!(Config::from_args(args(&["--dispute-window", "-1", "tx.csv"])).is_err());
+        assert_eq!(
+            Config::from_args(args(&["--skip", "10", "tx.csv"]))
+                .unwrap()
+                .skip,
+            10
+        );
+        let config = Config::from_args(args(&["--precision", "reject", "tx.csv"])).unwrap();
+        assert_eq!(config.policy.precision, Precision::Reject);
+        let config = Config::from_args(args(&["--minor-units", "2", "tx.csv"])).unwrap();
+        assert_eq!(
+            (config.policy.decimal_places, config.policy.precision),
+            (2, Precision::Reject)
+        );
+        assert!(Config::from_args(args(&["--minor-units", "29", "tx.csv"])).is_err());
+        for options in [
+            ["--minor-units", "2", "--precision", "round"],
+            ["--precision", "round", "--minor-units", "2"],
+        ] {
+            let config = Config::from_args(args(&[&options[..], &["tx.csv"]].concat())).unwrap();
+            assert_eq!(config.policy.precision, Precision::Round);
+        }
+        let config = Config::from_args(args(&["--require-ordered", "client", "tx.csv"])).unwrap();
+        assert_eq!(config.policy.ordered_tx_ids, Some(TxOrder::PerClient));
+        let config = Config::from_args(args(&["--freeze-after", "3", "tx.csv"])).unwrap();
+        assert_eq!(config.policy.freeze_after, 3);
+        assert!(Config::from_args(args(&["--freeze-after", "0", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--as-of", "42", "tx.csv"])).unwrap();
+        assert_eq!(config.as_of, Some(42));
+        assert!(Config::from_args(args(&["--as-of", "-1", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--max-errors", "10", "tx.csv"])).unwrap();
+        assert_eq!(config.max_errors, Some(10));
+        assert!(Config::from_args(args(&["--max-errors", "0", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--follow", "0.5", "tx.csv"])).unwrap();
+        assert_eq!(config.follow, Some(Duration::from_millis(500)));
+        assert!(Config::from_args(args(&["--follow", "0", "tx.csv"])).is_err());
+        assert!(
+            Config::from_args(args(&["--accounting-negatives", "tx.csv"]))
+                .unwrap()
+                .accounting_negatives
+        );
+        let config = Config::from_args(args(&["--max-integer-digits", "12", "tx.csv"])).unwrap();
+        assert_eq!(config.max_integer_digits, Some(12));
+        assert!(Config::from_args(args(&["--max-integer-digits", "0", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--no-header", "tx.csv"])).unwrap();
+        assert_eq!(
+            config.csv,
+            InputConfig::builder().has_headers(false).build()
+        );
+        // the last toggle wins
+        let config = Config::from_args(args(&["--no-header", "--header", "tx.csv"])).unwrap();
+        assert_eq!(config.csv, InputConfig::default());
+        assert!(Config::from_args(args(&["--follow", "1", "a.csv", "b.csv"])).is_err());
+        assert!(Config::from_args(args(&["--follow", "1", "--two-pass", "tx.csv"])).is_err());
+        let config = Config::from_args(args(&["--separate", "a.csv", "b.csv"])).unwrap();
+        assert!(config.separate);
+        assert!(Config::from_args(args(&["--separate", "--trace", "t.csv", "a.csv"])).is_err());
+        assert_eq!(
+            config.inputs,
+            vec![PathBuf::from("a.csv"), PathBuf::from("b.csv")]
+        );
+        let config =
+            Config::from_args(args(&["--withdrawal-disputes", "credit", "tx.csv"])).unwrap();
+        assert_eq!(config.policy.withdrawal_disputes, WithdrawalDispute::Credit);
+
+        assert!(Config::from_args_env(args(&["--mmap"]), None).is_err());
+        assert!(Config::from_args(args(&["--histogram", "many", "tx.csv"])).is_err());
+        assert!(Config::from_args(args(&["csv_files/tx.csv", "--trim"])).is_err());
+        assert!(Config::from_args(args(&["csv_files/tx.csv", "--trim", "left"])).is_err());
+        assert!(Config::from_args(args(&["--unknown", "csv_files/tx.csv"])).is_err());
+    }
+
+    #[test]
+    fn input_env() {
+        let env_input = || Some(OsString::from("csv_files/env.csv"));
+        let config = Config::from_args_env(args(&["--mmap"]), env_input()).unwrap();
+        assert_eq!(config.inputs, vec![PathBuf::from("csv_files/env.csv")]);
+
+        // a path given as argument takes precedence
+        let config = Config::from_args_env(args(&["tx.csv"]), env_input()).unwrap();
+        assert_eq!(config.inputs, vec![PathBuf::from("tx.csv")]);
+
+        let err = Config::from_args_env(args(&[]), Some(OsString::new())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unable to get arguments, file.csv expected as argument or in PAYMENT_ENGINE_INPUT"
+        );
+    }
+
+    #[test]
+    fn input_config() {
+        let data = "deposit; 1; 1; 10.0\ndispute; 1; 1\n";
+        let records = |config: InputConfig| {
+            config
+                .from_reader(data.as_bytes())
+                .records()
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        // the default options expect a comma delimited file with a header
+        let header_config = InputConfig::builder().delimiter(b';').build();
+        assert_eq!(
+            records(header_config).unwrap(),
+            vec![StringRecord::from(vec!["dispute", "1", "1"])]
+        );
+
+        let config = InputConfig::builder()
+            .delimiter(b';')
+            .has_headers(false)
+            .trim(Trim::None)
+            .build();
+        assert_eq!(
+            records(config.clone()).unwrap(),
+            vec![
+                StringRecord::from(vec!["deposit", " 1", " 1", " 10.0"]),
+                StringRecord::from(vec!["dispute", " 1", " 1"]),
+            ]
+        );
+
+        // records with a different number of fields are rejected unless flexible
+        let strict_config = InputConfig::builder()
+            .delimiter(b';')
+            .has_headers(false)
+            .flexible(false)
+            .build();
+        assert!(records(strict_config).is_err());
+    }
+}
@@ -0,0 +1,1458 @@
+use anyhow::{bail, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    io::Write,
+};
+
+use crate::{
+    account::{format_scaled, Account, AccountDiff, SystemTotals},
+    client::{Client, DisputeEvent},
+    policy::{Policy, Precision, TxOrder},
+    transaction::{Transaction, TransactionEnum},
+};
+
+/// Holds every client account and applies transactions to them
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PaymentEngine {
+    /// store client ids and its data based on transactrions it receives
+    clients: BTreeMap<u16, Client>,
+    /// rules applied when processing transactions, they aren't part of the
+    /// saved state
+    #[serde(skip)]
+    policy: Policy,
+    /// transaction IDs of every deposit and withdrawal, only tracked when
+    /// they must be unique
+    tx_ids: HashSet<u32>,
+    /// ID of the most recent deposit or withdrawal of any client
+    previous_tx_id: Option<u32>,
+    /// idempotency keys of the applied transactions
+    idempotency_keys: HashSet<String>,
+    /// number of applied transactions, only counted with a dispute window
+    #[serde(default)]
+    sequence: u64,
+    /// clients ended by an `end_client` marker, only their final balances are
+    /// kept and they don't accept further transactions
+    #[serde(default)]
+    ended: BTreeMap<u16, Ended>,
+}
+
+/// A client taken from the engine of a worker to be inserted in another one,
+/// e.g. so a transfer between them is applied by a single engine
+#[derive(Debug)]
+pub(crate) struct MovedClient {
+    /// ID of the client
+    client_id: u16,
+    /// the client, `None` if it never had a transaction or it was ended
+    client: Option<Client>,
+    /// the final balances of an ended client
+    ended: Option<Ended>,
+}
+
+impl MovedClient {
+    /// Returns the total balance the client moves between the engines
+    pub(crate) fn total(&self) -> Decimal {
+        let ended = self.ended.map(|ended| ended.account.total);
+        self.client
+            .as_ref()
+            .map(Client::total)
+            .or(ended)
+            .unwrap_or_default()
+    }
+}
+
+/// What's kept of a client ended by an `end_client` marker, the rest of it's
+/// history is freed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Ended {
+    /// final balances of the client
+    account: Account,
+    /// group the client was tagged into
+    group_id: Option<u32>,
+}
+
+impl PaymentEngine {
+    /// Returns a new engine without any client
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a new engine without any client applying the given policy
+    pub fn with_policy(policy: Policy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Removes every client and the IDs and keys of the applied transactions,
+    /// the engine is reused for an independent ledger, e.g. the next file of
+    /// `--separate`, keeping its policy and the capacity of its sets
+    pub fn reset(&mut self) {
+        self.clients.clear();
+        self.tx_ids.clear();
+        self.previous_tx_id = None;
+        self.idempotency_keys.clear();
+        self.sequence = 0;
+        self.ended.clear();
+    }
+
+    /// Replaces the rules applied to the following transactions
+    pub(crate) fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+
+    /// Applies a transaction to the client it belongs to, a client is created
+    /// by its first transaction
+    pub fn process(&mut self, mut tx: Transaction) -> Result<()> {
+        if tx.tx_type == TransactionEnum::EndClient {
+            return self.end_client(tx.client_id).map(drop);
+        }
+        let destination_id = tx
+            .destination_id
+            .filter(|_| tx.tx_type == TransactionEnum::Transfer);
+        for client_id in std::iter::once(tx.client_id).chain(destination_id) {
+            self.check_ended(client_id)?;
+        }
+        // trailing zeros, e.g. `1.50000`, don't exceed the precision
+        let decimal_places = self.policy.decimal_places;
+        if tx.tx_amount.normalize().scale() > decimal_places {
+            match self.policy.precision {
+                Precision::Reject => bail!(
+                    "Amount {} of transaction ID: {} has more than {decimal_places} decimal places!",
+                    tx.tx_amount,
+                    tx.tx_id
+                ),
+                Precision::Round => {
+                    let rounded = tx.tx_amount.round_dp(decimal_places);
+                    if rounded.is_zero() {
+                        log::warn!(
+                            "Amount {} of transaction ID: {} rounds to zero!",
+                            tx.tx_amount,
+                            tx.tx_id
+                        );
+                    }
+                    tx.tx_amount = rounded;
+                }
+            }
+        }
+        let new_tx = matches!(
+            tx.tx_type,
+            TransactionEnum::Deposit | TransactionEnum::Withdrawal | TransactionEnum::Transfer
+        );
+        if new_tx {
+            self.check_order(&tx)?;
+        }
+        if self.policy.unique_tx_ids && new_tx && self.tx_ids.contains(&tx.tx_id) {
+            bail!("Transaction ID: {} was already used!", tx.tx_id);
+        }
+        // a replayed delivery of an applied transaction, a failed one may be retried
+        if let Some(key) = &tx.idempotency_key {
+            if self.idempotency_keys.contains(key) {
+                bail!("Transaction with idempotency key {key} was already applied!");
+            }
+        }
+
+        // guard the memory against inputs referencing countless clients
+        if let Some(max_clients) = self.policy.max_clients {
+            if self.clients.len() >= max_clients && !self.clients.contains_key(&tx.client_id) {
+                bail!(
+                    "Client ID: {} was not created, the limit of {max_clients} clients was reached!",
+                    tx.client_id
+                );
+            }
+        }
+        if tx.tx_type == TransactionEnum::Dispute {
+            self.check_dispute_window(tx.client_id, tx.tx_id)?;
+        }
+        // a transfer may still create it's destination, it's credited like a deposit
+        if self.policy.require_account_creation
+            && tx.tx_type != TransactionEnum::Deposit
+            && !self.clients.contains_key(&tx.client_id)
+        {
+            bail!(
+                "Client ID: {} doesn't exist, only a deposit creates a client!",
+                tx.client_id
+            );
+        }
+        if tx.tx_type == TransactionEnum::Transfer {
+            self.transfer(&tx)?;
+        } else {
+            // a client is created on its first transaction, which is then applied
+            // like every following one
+            self.clients.entry(tx.client_id).or_default().process_tx(
+                tx.tx_id,
+                tx.tx_type,
+                tx.tx_amount,
+                &self.policy,
+            )?;
+        }
+        // only an applied transaction uses its ID, a rejected one may be corrected
+        // and sent again
+        if new_tx {
+            self.previous_tx_id = Some(tx.tx_id);
+            if self.policy.unique_tx_ids {
+                self.tx_ids.insert(tx.tx_id);
+            }
+        }
+        if self.policy.dispute_window.is_some() {
+            self.sequence += 1;
+            // a transfer is recorded by both of its clients
+            if new_tx {
+                for client_id in std::iter::once(tx.client_id).chain(destination_id) {
+                    if let Some(client) = self.clients.get_mut(&client_id) {
+                        client.set_sequence(tx.tx_id, self.sequence);
+                    }
+                }
+            }
+        }
+        // an applied transaction tags its client, the latest tag wins
+        if let Some(group_id) = tx.group_id {
+            if let Some(client) = self.clients.get_mut(&tx.client_id) {
+                client.set_group(group_id);
+            }
+        }
+        if let Some(key) = tx.idempotency_key {
+            self.idempotency_keys.insert(key);
+        }
+        Ok(())
+    }
+
+    /// Moves the amount of a transfer from its client to the destination one, a
+    /// withdrawal from the source and a deposit to the destination recorded
+    /// under the transfer's ID, each one can be disputed by its client.
+    ///
+    /// The transfer is atomic, everything that could fail the deposit is checked
+    /// before the withdrawal so a rejected transfer leaves both accounts unchanged
+    fn transfer(&mut self, tx: &Transaction) -> Result<()> {
+        let Some(destination_id) = tx.destination_id else {
+            bail!("Transfer ID: {} has no destination client!", tx.tx_id);
+        };
+        // a negative amount would credit the source and be rejected by the
+        // destination as a correction
+        if tx.tx_amount <= Decimal::ZERO {
+            bail!(
+                "Transfer ID: {} has an amount of {}, it must be positive!",
+                tx.tx_id,
+                tx.tx_amount
+            );
+        }
+        if destination_id == tx.client_id {
+            bail!(
+                "Transfer ID: {} has the same source and destination client!",
+                tx.tx_id
+            );
+        }
+        match self.clients.get(&destination_id) {
+            Some(destination) if !self.policy.allow_frozen_deposits => {
+                destination.account_frozen(tx.tx_id)?;
+            }
+            Some(_) => {}
+            None => {
+                if let Some(max_clients) = self.policy.max_clients {
+                    let source_created = !self.clients.contains_key(&tx.client_id);
+                    if self.clients.len() + usize::from(source_created) >= max_clients {
+                        bail!(
+                            "Client ID: {destination_id} was not created, the limit of {max_clients} clients was reached!"
+                        );
+                    }
+                }
+            }
+        }
+
+        self.clients.entry(tx.client_id).or_default().process_tx(
+            tx.tx_id,
+            TransactionEnum::Withdrawal,
+            tx.tx_amount,
+            &self.policy,
+        )?;
+        self.clients.entry(destination_id).or_default().process_tx(
+            tx.tx_id,
+            TransactionEnum::Deposit,
+            tx.tx_amount,
+            &self.policy,
+        )
+    }
+
+    /// Applies a transaction like [`PaymentEngine::process`] and writes to `out`
+    /// a step log line with the client's balances before and after it and the
+    /// outcome, e.g.
+    /// `dispute client 1 tx 1: 10.0000/0.0000/10.0000 -> 0.0000/10.0000/10.0000, applied`,
+    /// the balances are `available/held/total`
+    pub fn process_explained<W: Write>(&mut self, tx: Transaction, out: &mut W) -> Result<()> {
+        let (tx_type, client_id, tx_id) = (tx.tx_type, tx.client_id, tx.tx_id);
+        let before = self.balances(client_id);
+        let result = self.process(tx);
+        let outcome = match &result {
+            Ok(()) => "applied".to_string(),
+            Err(err) => {
+                let reason = err.to_string();
+                format!("declined, {}", reason.lines().next().unwrap_or_default())
+            }
+        };
+        let _ = writeln!(
+            out,
+            "{tx_type} client {client_id} tx {tx_id}: {before} -> {}, {outcome}",
+            self.balances(client_id)
+        );
+        result
+    }
+
+    /// Returns the balances of a client formatted as `available/held/total`
+    fn balances(&self, client_id: u16) -> String {
+        let account = self
+            .account(client_id)
+            .unwrap_or_else(|| Client::default().snapshot(client_id));
+        format!(
+            "{}/{}/{}",
+            format_scaled(account.available, self.policy.decimal_places),
+            format_scaled(account.held, self.policy.decimal_places),
+            format_scaled(account.total, self.policy.decimal_places)
+        )
+    }
+
+    /// Checks that a client wasn't ended, it doesn't accept any transaction
+    fn check_ended(&self, client_id: u16) -> Result<()> {
+        if self.ended.contains_key(&client_id) {
+            bail!("Client ID: {client_id} has ended, it doesn't accept further transactions!");
+        }
+        Ok(())
+    }
+
+    /// Checks that a deposit or withdrawal ID is greater than the previous one,
+    /// of any client or of the same client depending on the policy
+    fn check_order(&self, tx: &Transaction) -> Result<()> {
+        let previous_tx_id = match self.policy.ordered_tx_ids {
+            None => return Ok(()),
+            Some(TxOrder::Global) => self.previous_tx_id,
+            Some(TxOrder::PerClient) => self
+                .clients
+                .get(&tx.client_id)
+                .filter(|client| client.has_transactions())
+                .map(|client| client.last_tx_id()),
+        };
+        // a transfer is also the latest transaction of its destination
+        let destination_tx_id = match self.policy.ordered_tx_ids {
+            Some(TxOrder::PerClient) => tx
+                .destination_id
+                .and_then(|destination_id| self.clients.get(&destination_id))
+                .filter(|client| client.has_transactions())
+                .map(|client| client.last_tx_id()),
+            _ => None,
+        };
+        match previous_tx_id.max(destination_tx_id) {
+            Some(previous_tx_id) if tx.tx_id <= previous_tx_id => bail!(
+                "Transaction ID: {} is out of order, the previous one is {previous_tx_id}!",
+                tx.tx_id
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that a dispute is filed within the configured number of
+    /// transactions after the one of its client it references
+    fn check_dispute_window(&self, client_id: u16, tx_id: u32) -> Result<()> {
+        let Some(window) = self.policy.dispute_window else {
+            return Ok(());
+        };
+        let sequence = self
+            .clients
+            .get(&client_id)
+            .and_then(|client| client.sequence(tx_id));
+        match sequence {
+            Some(sequence) if self.sequence - sequence > window => bail!(
+                "Dispute of transaction ID: {tx_id} is stale, {} transactions were applied since, the window is {window}!",
+                self.sequence - sequence
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the change of the sum of the total balances the transaction makes
+    /// if it's applied, it's derived from the transaction and the accounts
+    /// independently of how it's applied
+    pub(crate) fn total_change(&self, tx: &Transaction) -> Decimal {
+        match tx.tx_type {
+            TransactionEnum::Deposit => tx.tx_amount.round_dp(self.policy.decimal_places),
+            TransactionEnum::Withdrawal => -tx.tx_amount.round_dp(self.policy.decimal_places),
+            // the funds only move between clients
+            TransactionEnum::Transfer => Decimal::ZERO,
+            // the balances of the client are kept as they are
+            TransactionEnum::EndClient => Decimal::ZERO,
+            tx_type => self
+                .clients
+                .get(&tx.client_id)
+                .map_or(Decimal::ZERO, |client| {
+                    client.total_change(tx.tx_id, tx_type, &self.policy)
+                }),
+        }
+    }
+
+    /// Returns the balance changes from this state to `other` of every client
+    /// whose account differs, ordered by client ID. Identical states have no
+    /// difference
+    pub fn diff(&self, other: &PaymentEngine) -> Vec<AccountDiff> {
+        let client_ids: BTreeSet<u16> = self
+            .accounts_iter()
+            .chain(other.accounts_iter())
+            .map(|account| account.client)
+            .collect();
+        let snapshot = |engine: &PaymentEngine, client_id| {
+            engine
+                .account(client_id)
+                .unwrap_or_else(|| Client::default().snapshot(client_id))
+        };
+        client_ids
+            .into_iter()
+            .map(|client_id| {
+                let (before, after) = (snapshot(self, client_id), snapshot(other, client_id));
+                AccountDiff {
+                    client: client_id,
+                    available: after.available - before.available,
+                    held: after.held - before.held,
+                    total: after.total - before.total,
+                    locked_changed: after.locked != before.locked,
+                }
+            })
+            .filter(|diff| {
+                !diff.available.is_zero()
+                    || !diff.held.is_zero()
+                    || !diff.total.is_zero()
+                    || diff.locked_changed
+            })
+            .collect()
+    }
+
+    /// Returns the available, held and total balances summed across every
+    /// client
+    pub fn system_totals(&self) -> SystemTotals {
+        self.accounts_iter()
+            .fold(SystemTotals::default(), |totals, account| SystemTotals {
+                total_available: totals.total_available + account.available,
+                total_held: totals.total_held + account.held,
+                total: totals.total + account.total,
+            })
+    }
+
+    /// Returns the sum of the total balances of every client
+    pub(crate) fn total(&self) -> Decimal {
+        self.accounts_iter().map(|account| account.total).sum()
+    }
+
+    /// Checks that the sum of the total balances of every client matches the
+    /// expected one, e.g. the deposits minus the withdrawals and chargebacks
+    /// applied. Amounts are exact decimals so any difference is reported
+    pub fn reconcile(&self, expected: Decimal) -> Result<()> {
+        let total = self.total();
+        if total != expected {
+            bail!(
+                "Reconciliation failed! The total balances sum to {total} instead of {expected}, a difference of {}",
+                total - expected
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the sum of the total balances of the clients of every group
+    /// ordered by group ID, the untagged clients aren't part of any group
+    pub fn group_totals(&self) -> BTreeMap<u32, Decimal> {
+        let mut totals = BTreeMap::new();
+        let clients = self
+            .clients
+            .values()
+            .map(|client| (client.group(), client.total()));
+        let ended = self
+            .ended
+            .values()
+            .map(|ended| (ended.group_id, ended.account.total));
+        for (group_id, total) in clients.chain(ended) {
+            if let Some(group_id) = group_id {
+                *totals.entry(group_id).or_insert(Decimal::ZERO) += total;
+            }
+        }
+        totals
+    }
+
+    /// Unfreezes the account of a client, the policy decides whether its held
+    /// funds are released.
+    ///
+    /// This is an administrative operation out of band of the transactions,
+    /// there's no transaction type unfreezing an account
+    pub fn unfreeze(&mut self, client_id: u16) -> Result<()> {
+        match self.clients.get_mut(&client_id) {
+            Some(client) => client.unfreeze(&self.policy),
+            None => bail!("Client ID: {client_id} does not exist!"),
+        }
+    }
+
+    /// Returns a snapshot of every client account ordered by client ID
+    pub fn accounts(&self) -> Vec<Account> {
+        self.accounts_iter().collect()
+    }
+
+    /// Returns the snapshots of the client accounts ordered by client ID, they
+    /// are taken lazily without collecting them. The ended clients are merged
+    /// in with their final balances
+    pub fn accounts_iter(&self) -> impl Iterator<Item = Account> + '_ {
+        let mut clients = self
+            .clients
+            .iter()
+            .map(|(client_id, client)| client.snapshot(*client_id))
+            .peekable();
+        let mut ended = self.ended.values().map(|ended| ended.account).peekable();
+        std::iter::from_fn(move || match (clients.peek(), ended.peek()) {
+            (Some(client), Some(ended_client)) if ended_client.client < client.client => {
+                ended.next()
+            }
+            (Some(_), _) => clients.next(),
+            (None, _) => ended.next(),
+        })
+    }
+
+    /// Returns the snapshot of a client's account, the final one of an ended
+    /// client, `None` if it never had a transaction
+    pub(crate) fn account(&self, client_id: u16) -> Option<Account> {
+        match self.clients.get(&client_id) {
+            Some(client) => Some(client.snapshot(client_id)),
+            None => self.ended.get(&client_id).map(|ended| ended.account),
+        }
+    }
+
+    /// Returns `true` if a deposit or withdrawal of the client was applied,
+    /// an ended client always had one
+    pub(crate) fn has_transactions(&self, client_id: u16) -> bool {
+        self.ended.contains_key(&client_id)
+            || self
+                .clients
+                .get(&client_id)
+                .is_some_and(|client| client.has_transactions())
+    }
+
+    /// Returns the account of a client, `None` if it never had a transaction
+    /// or it was ended
+    pub fn client(&self, client_id: u16) -> Option<&Client> {
+        self.clients.get(&client_id)
+    }
+
+    /// Returns the dispute events of a client in chronological order, empty if
+    /// it never had a transaction
+    pub fn dispute_history(&self, client_id: u16) -> &[DisputeEvent] {
+        self.clients
+            .get(&client_id)
+            .map_or(&[], |client| client.dispute_history())
+    }
+
+    /// Returns the disputed transactions of a client holding funds and the
+    /// value each one holds, empty if it never had a transaction
+    pub fn held_breakdown(&self, client_id: u16) -> Vec<(u32, Decimal)> {
+        self.clients
+            .get(&client_id)
+            .map_or(vec![], |client| client.held_breakdown())
+    }
+
+    /// Returns the ID of the most recent deposit or withdrawal of a client
+    pub fn last_tx_id(&self, client_id: u16) -> Option<u32> {
+        self.account(client_id).map(|account| account.last_tx_id)
+    }
+
+    /// Removes a client from the engine returning its final snapshot, the next
+    /// transaction for the same client ID creates a fresh client
+    pub fn drop_client(&mut self, client_id: u16) -> Option<Account> {
+        self.clients
+            .remove(&client_id)
+            .map(|client| client.snapshot(client_id))
+    }
+
+    /// Removes a client to insert it in another engine with
+    /// [`PaymentEngine::insert_client`], an ended one stays ended
+    pub(crate) fn take_client(&mut self, client_id: u16) -> MovedClient {
+        MovedClient {
+            client_id,
+            client: self.clients.remove(&client_id),
+            ended: self.ended.remove(&client_id),
+        }
+    }
+
+    /// Inserts a client taken from another engine, it's following
+    /// transactions are applied like in the original one
+    pub(crate) fn insert_client(&mut self, moved: MovedClient) {
+        if let Some(client) = moved.client {
+            self.clients.insert(moved.client_id, client);
+        }
+        if let Some(ended) = moved.ended {
+            self.ended.insert(moved.client_id, ended);
+        }
+    }
+
+    /// Ends a client that has no further transactions returning its final
+    /// snapshot. Only the snapshot is kept, it's part of the accounts like any
+    /// other, and the following transactions of the client are rejected
+    pub fn end_client(&mut self, client_id: u16) -> Result<Account> {
+        self.check_ended(client_id)?;
+        // a client only created by rejected transactions has nothing to end
+        let account = match self.clients.get(&client_id) {
+            Some(client) if client.has_transactions() => client.snapshot(client_id),
+            _ => bail!("Client ID: {client_id} has no transactions to end!"),
+        };
+        let group_id = self
+            .clients
+            .remove(&client_id)
+            .and_then(|client| client.group());
+        self.ended.insert(client_id, Ended { account, group_id });
+        Ok(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PaymentEngine;
+    use crate::{
+        account::{Account, AccountDiff, SystemTotals},
+        client::DisputeEvent,
+        config::InputConfig,
+        input::Input,
+        policy::{Policy, Precision, TxOrder, WithdrawalDispute},
+        transaction::{Transaction, TransactionEnum},
+    };
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use std::fs::File;
+
+    fn tx(tx_type: TransactionEnum, client_id: u16, tx_id: u32, tx_amount: Decimal) -> Transaction {
+        Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            tx_amount,
+            timestamp: None,
+            idempotency_key: None,
+            destination_id: None,
+            group_id: None,
+        }
+    }
+
+    #[test]
+    fn require_account_creation() {
+        let mut engine = PaymentEngine::with_policy(Policy {
+            require_account_creation: true,
+            ..Default::default()
+        });
+        let err = engine
+            .process(tx(TransactionEnum::Withdrawal, 1, 1, dec!(1.0)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Client ID: 1 doesn't exist, only a deposit creates a client!"
+        );
+        assert!(engine
+            .process(tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO))
+            .is_err());
+        assert!(engine.client(1).is_none());
+
+        // once created by a deposit the client accepts every transaction
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 2, dec!(5.0)))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Withdrawal, 1, 3, dec!(1.0)))
+            .unwrap();
+        assert_eq!(
+            engine.client(1).map(|client| client.available()),
+            Some(dec!(4.0))
+        );
+
+        // by default the withdrawal creates an empty account before it's rejected
+        let mut engine = PaymentEngine::new();
+        assert!(engine
+            .process(tx(TransactionEnum::Withdrawal, 1, 1, dec!(1.0)))
+            .is_err());
+        assert!(engine.client(1).is_some());
+    }
+
+    #[test]
+    fn dispute_window() {
+        let mut engine = PaymentEngine::with_policy(Policy {
+            dispute_window: Some(2),
+            ..Default::default()
+        });
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Deposit, 2, 2, dec!(5.0)))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 3, dec!(1.0)))
+            .unwrap();
+        // two transactions were applied since deposit 1, it's within the window
+        engine
+            .process(tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Resolve, 1, 1, Decimal::ZERO))
+            .unwrap();
+
+        // another client reusing the ID doesn't refresh deposit 2, four
+        // transactions were applied since
+        engine
+            .process(tx(TransactionEnum::Deposit, 3, 2, dec!(1.0)))
+            .unwrap();
+        let err = engine
+            .process(tx(TransactionEnum::Dispute, 2, 2, Decimal::ZERO))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Dispute of transaction ID: 2 is stale, 4 transactions were applied since, the window is 2!"
+        );
+        assert_eq!(
+            engine.client(2).map(|client| client.held()),
+            Some(Decimal::ZERO)
+        );
+        engine
+            .process(tx(TransactionEnum::Dispute, 3, 2, Decimal::ZERO))
+            .unwrap();
+
+        // the sequences are evicted with the capped history
+        let mut engine = PaymentEngine::with_policy(Policy {
+            dispute_window: Some(2),
+            max_tx_per_client: Some(1),
+            ..Default::default()
+        });
+        for tx_id in 1..=3 {
+            engine
+                .process(tx(TransactionEnum::Deposit, 1, tx_id, dec!(1.0)))
+                .unwrap();
+        }
+        let client = engine.client(1).unwrap();
+        assert_eq!(client.sequence(1), None);
+        assert_eq!(client.sequence(3), Some(3));
+    }
+
+    #[test]
+    fn drop_client() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Deposit, 2, 2, dec!(5.0)))
+            .unwrap();
+
+        assert_eq!(
+            engine.drop_client(1),
+            Some(Account {
+                client: 1,
+                available: Decimal::ZERO,
+                held: dec!(10.0),
+                total: dec!(10.0),
+                locked: false,
+                last_tx_id: 1,
+                transactions: 2,
+            })
+        );
+        assert_eq!(engine.drop_client(1), None);
+        assert_eq!(
+            engine.client(2).map(|client| client.available()),
+            Some(dec!(5.0))
+        );
+
+        // a new transaction for the dropped id starts from a fresh client
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 3, dec!(2.0)))
+            .unwrap();
+        let mut accounts = engine.accounts();
+        accounts.sort();
+        assert_eq!(accounts[0].available, dec!(2.0));
+        assert_eq!(accounts[0].held, Decimal::ZERO);
+        assert_eq!(accounts.len(), 2);
+    }
+
+    #[test]
+    fn end_client() {
+        let mut engine = PaymentEngine::new();
+        for transaction in [
+            tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)),
+            tx(TransactionEnum::Deposit, 2, 2, dec!(5.0)),
+            tx(TransactionEnum::Deposit, 3, 3, dec!(2.0)),
+            tx(TransactionEnum::EndClient, 2, 4, Decimal::ZERO),
+            tx(TransactionEnum::Deposit, 1, 5, dec!(1.0)),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+
+        // the ended client is only kept as it's final balances, in client order
+        assert!(engine.client(2).is_none());
+        assert_eq!(
+            engine
+                .accounts_iter()
+                .map(|account| (account.client, account.total))
+                .collect::<Vec<_>>(),
+            vec![(1, dec!(11.0)), (2, dec!(5.0)), (3, dec!(2.0))]
+        );
+        assert_eq!(engine.last_tx_id(2), Some(2));
+        assert_eq!(
+            engine.system_totals(),
+            SystemTotals {
+                total_available: dec!(18.0),
+                total_held: Decimal::ZERO,
+                total: dec!(18.0),
+            }
+        );
+        assert!(engine.reconcile(dec!(18.0)).is_ok());
+
+        // it doesn't accept any further transaction, not even another marker
+        let err = engine
+            .process(tx(TransactionEnum::Deposit, 2, 6, dec!(1.0)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Client ID: 2 has ended, it doesn't accept further transactions!"
+        );
+        assert!(engine
+            .process(tx(TransactionEnum::EndClient, 2, 7, Decimal::ZERO))
+            .is_err());
+        // a client without transactions has nothing to end
+        assert!(engine
+            .process(tx(TransactionEnum::EndClient, 4, 8, Decimal::ZERO))
+            .is_err());
+    }
+
+    #[test]
+    fn unique_tx_ids() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Deposit, 2, 1, dec!(10.0)))
+            .unwrap();
+
+        let mut engine = PaymentEngine::with_policy(Policy {
+            unique_tx_ids: true,
+            ..Default::default()
+        });
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)))
+            .unwrap();
+        assert!(engine
+            .process(tx(TransactionEnum::Deposit, 2, 1, dec!(10.0)))
+            .is_err());
+        assert!(engine
+            .process(tx(TransactionEnum::Withdrawal, 1, 1, dec!(5.0)))
+            .is_err());
+        // disputes reference a past transaction ID
+        engine
+            .process(tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO))
+            .unwrap();
+        // a rejected transaction doesn't use its ID, e.g. a withdrawal lacking
+        // funds or a replay under a new ID
+        assert!(engine
+            .process(tx(TransactionEnum::Withdrawal, 1, 2, dec!(50.0)))
+            .is_err());
+        let mut replay = tx(TransactionEnum::Deposit, 2, 3, dec!(1.0));
+        replay.idempotency_key = Some("a".to_string());
+        engine.process(replay.clone()).unwrap();
+        replay.tx_id = 4;
+        assert!(engine.process(replay).is_err());
+        engine
+            .process(tx(TransactionEnum::Deposit, 2, 2, dec!(2.0)))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Deposit, 2, 4, dec!(2.0)))
+            .unwrap();
+
+        let account = engine.drop_client(1).unwrap();
+        assert_eq!(
+            (account.available, account.held),
+            (Decimal::ZERO, dec!(10.0))
+        );
+        // the rejected deposit never reached the second client, only its later ones
+        assert_eq!(engine.drop_client(2).unwrap().available, dec!(5.0));
+    }
+
+    #[test]
+    fn first_deposit() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(10.5)))
+            .unwrap();
+        // client 2 is created by a failing dispute before its first deposit
+        assert!(engine
+            .process(tx(TransactionEnum::Dispute, 2, 2, Decimal::ZERO))
+            .is_err());
+        engine
+            .process(tx(TransactionEnum::Deposit, 2, 2, dec!(10.5)))
+            .unwrap();
+
+        // the first deposit is applied exactly like the following ones
+        for client_id in [1, 2] {
+            engine
+                .process(tx(
+                    TransactionEnum::Deposit,
+                    client_id,
+                    client_id as u32 + 2,
+                    dec!(1.25),
+                ))
+                .unwrap();
+        }
+        let (first, second) = (engine.client(1).unwrap(), engine.client(2).unwrap());
+        assert_eq!(
+            (first.available(), first.held(), first.total()),
+            (second.available(), second.held(), second.total())
+        );
+        assert_eq!(first.total(), dec!(11.75));
+    }
+
+    #[test]
+    fn unfreeze() {
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Chargeback, 1, 1, Decimal::ZERO))
+            .unwrap();
+        assert!(engine
+            .process(tx(TransactionEnum::Deposit, 1, 2, dec!(1.0)))
+            .is_err());
+
+        engine.unfreeze(1).unwrap();
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 2, dec!(1.0)))
+            .unwrap();
+        assert_eq!(engine.client(1).unwrap().total(), dec!(1.0));
+        assert!(engine.unfreeze(2).is_err());
+    }
+
+    #[test]
+    fn sorted_accounts() {
+        let mut engine = PaymentEngine::new();
+        for client_id in [3, 1, 2] {
+            engine
+                .process(tx(
+                    TransactionEnum::Deposit,
+                    client_id,
+                    client_id as u32,
+                    dec!(1.0),
+                ))
+                .unwrap();
+        }
+
+        let mut accounts = engine.accounts();
+        accounts.sort();
+        assert_eq!(
+            accounts,
+            [1, 2, 3]
+                .map(|client| Account {
+                    client,
+                    available: dec!(1.0),
+                    held: Decimal::ZERO,
+                    total: dec!(1.0),
+                    locked: false,
+                    last_tx_id: client as u32,
+                    transactions: 1,
+                })
+                .to_vec()
+        );
+        assert!(accounts[0] < accounts[1]);
+    }
+
+    #[test]
+    fn ordered_tx_ids() {
+        let ordered = |order| {
+            let mut engine = PaymentEngine::with_policy(Policy {
+                ordered_tx_ids: Some(order),
+                ..Default::default()
+            });
+            engine
+                .process(tx(TransactionEnum::Deposit, 1, 5, dec!(10.0)))
+                .unwrap();
+            engine
+                .process(tx(TransactionEnum::Deposit, 2, 7, dec!(10.0)))
+                .unwrap();
+            // disputes reference a past transaction ID
+            engine
+                .process(tx(TransactionEnum::Dispute, 1, 5, Decimal::ZERO))
+                .unwrap();
+            engine
+        };
+
+        let mut engine = ordered(TxOrder::Global);
+        let err = engine
+            .process(tx(TransactionEnum::Deposit, 1, 6, dec!(1.0)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Transaction ID: 6 is out of order, the previous one is 7!"
+        );
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 8, dec!(1.0)))
+            .unwrap();
+
+        // per client only the IDs of the same client must be increasing
+        let mut engine = ordered(TxOrder::PerClient);
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 6, dec!(1.0)))
+            .unwrap();
+        assert!(engine
+            .process(tx(TransactionEnum::Withdrawal, 2, 3, dec!(1.0)))
+            .is_err());
+    }
+
+    #[test]
+    fn explain() {
+        let mut engine = PaymentEngine::new();
+        let mut out = vec![];
+        let steps = [
+            tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)),
+            tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO),
+            tx(TransactionEnum::Withdrawal, 1, 2, dec!(5.0)),
+            tx(TransactionEnum::Resolve, 1, 1, Decimal::ZERO),
+            tx(TransactionEnum::Chargeback, 1, 1, Decimal::ZERO),
+        ];
+        for step in steps {
+            let _ = engine.process_explained(step, &mut out);
+        }
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "deposit client 1 tx 1: 0.0000/0.0000/0.0000 -> 10.0000/0.0000/10.0000, applied
+dispute client 1 tx 1: 10.0000/0.0000/10.0000 -> 0.0000/10.0000/10.0000, applied
+withdrawal client 1 tx 2: 0.0000/10.0000/10.0000 -> 0.0000/10.0000/10.0000, declined, Not enough available balance to process withdrawal!
+resolve client 1 tx 1: 0.0000/10.0000/10.0000 -> 10.0000/0.0000/10.0000, applied
+chargeback client 1 tx 1: 10.0000/0.0000/10.0000 -> 10.0000/0.0000/10.0000, declined, cannot chargeback tx 1: not under dispute
+"
+        );
+    }
+
+    #[test]
+    fn accounts_iter() {
+        let mut engine = PaymentEngine::new();
+        for client_id in [5, 3, 9, 1] {
+            engine
+                .process(tx(
+                    TransactionEnum::Deposit,
+                    client_id,
+                    client_id as u32,
+                    dec!(1.0),
+                ))
+                .unwrap();
+        }
+
+        assert_eq!(engine.accounts_iter().count(), 4);
+        assert_eq!(
+            engine
+                .accounts_iter()
+                .map(|account| account.client)
+                .collect::<Vec<_>>(),
+            vec![1, 3, 5, 9]
+        );
+    }
+
+    #[test]
+    fn max_clients() {
+        let mut engine = PaymentEngine::with_policy(Policy {
+            max_clients: Some(2),
+            ..Default::default()
+        });
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Deposit, 2, 2, dec!(5.0)))
+            .unwrap();
+
+        let err = engine
+            .process(tx(TransactionEnum::Deposit, 3, 3, dec!(1.0)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Client ID: 3 was not created, the limit of 2 clients was reached!"
+        );
+        assert!(engine.client(3).is_none());
+        // the existing clients keep processing
+        engine
+            .process(tx(TransactionEnum::Withdrawal, 1, 4, dec!(2.5)))
+            .unwrap();
+        assert_eq!(engine.accounts().len(), 2);
+    }
+
+    #[test]
+    fn sub_scale_amounts() {
+        // the rounded value is the one applied
+        let mut engine = PaymentEngine::new();
+        for tx_id in 1..=3 {
+            engine
+                .process(tx(TransactionEnum::Deposit, 1, tx_id, dec!(0.00004)))
+                .unwrap();
+        }
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 4, dec!(1.23456)))
+            .unwrap();
+        assert_eq!(
+            engine.client(1).map(|client| client.available()),
+            Some(dec!(1.2346))
+        );
+
+        let mut engine = PaymentEngine::with_policy(Policy {
+            precision: Precision::Reject,
+            ..Default::default()
+        });
+        let err = engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(0.00001)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Amount 0.00001 of transaction ID: 1 has more than 4 decimal places!"
+        );
+        // trailing zeros don't exceed the precision
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 2, dec!(1.50000)))
+            .unwrap();
+    }
+
+    #[test]
+    fn dispute_history() {
+        let mut engine = PaymentEngine::new();
+        let steps = [
+            tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)),
+            tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO),
+            tx(TransactionEnum::Resolve, 1, 1, Decimal::ZERO),
+            // resolving again is rejected and not recorded
+            tx(TransactionEnum::Resolve, 1, 1, Decimal::ZERO),
+            tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO),
+            tx(TransactionEnum::Chargeback, 1, 1, Decimal::ZERO),
+        ];
+        for tx in steps {
+            let _ = engine.process(tx);
+        }
+        assert_eq!(
+            engine.dispute_history(1),
+            [
+                DisputeEvent::Opened(1),
+                DisputeEvent::Resolved(1),
+                DisputeEvent::Opened(1),
+                DisputeEvent::ChargedBack(1),
+            ]
+        );
+        assert!(engine.dispute_history(2).is_empty());
+    }
+
+    #[test]
+    fn resolved_then_withdrawn() {
+        let mut engine = PaymentEngine::new();
+        let balances = |engine: &PaymentEngine| {
+            let client = engine.client(1).unwrap();
+            (client.available(), client.held(), client.total())
+        };
+        let steps = [
+            (
+                tx(TransactionEnum::Deposit, 1, 1, dec!(100.1234)),
+                (dec!(100.1234), dec!(0), dec!(100.1234)),
+            ),
+            (
+                tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO),
+                (dec!(0), dec!(100.1234), dec!(100.1234)),
+            ),
+            // the funds are available again, the total never changed
+            (
+                tx(TransactionEnum::Resolve, 1, 1, Decimal::ZERO),
+                (dec!(100.1234), dec!(0), dec!(100.1234)),
+            ),
+            (
+                tx(TransactionEnum::Withdrawal, 1, 2, dec!(60.0234)),
+                (dec!(40.1), dec!(0), dec!(40.1)),
+            ),
+            (
+                tx(TransactionEnum::Withdrawal, 1, 3, dec!(40.1)),
+                (dec!(0), dec!(0), dec!(0)),
+            ),
+        ];
+        for (tx, expected) in steps {
+            engine.process(tx).unwrap();
+            assert_eq!(balances(&engine), expected);
+            assert!(engine.client(1).unwrap().invariants_hold());
+        }
+        // nothing is left to withdraw or to hold for a new dispute of the deposit
+        assert!(engine
+            .process(tx(TransactionEnum::Withdrawal, 1, 4, dec!(0.0001)))
+            .is_err());
+        assert!(engine
+            .process(tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO))
+            .is_err());
+        assert_eq!(balances(&engine), (dec!(0), dec!(0), dec!(0)));
+        assert!(!engine.client(1).unwrap().is_frozen());
+    }
+
+    #[test]
+    fn diff() {
+        let deposits = || {
+            let mut engine = PaymentEngine::new();
+            for client_id in 1..=3 {
+                engine
+                    .process(tx(
+                        TransactionEnum::Deposit,
+                        client_id,
+                        client_id.into(),
+                        dec!(10.0),
+                    ))
+                    .unwrap();
+            }
+            engine
+        };
+        // reprocessing the same transactions makes no difference
+        let (before, mut after) = (deposits(), deposits());
+        assert!(before.diff(&after).is_empty());
+
+        after
+            .process(tx(TransactionEnum::Dispute, 2, 2, Decimal::ZERO))
+            .unwrap();
+        after
+            .process(tx(TransactionEnum::Chargeback, 2, 2, Decimal::ZERO))
+            .unwrap();
+        assert_eq!(
+            before.diff(&after),
+            vec![AccountDiff {
+                client: 2,
+                available: dec!(-10.0),
+                held: Decimal::ZERO,
+                total: dec!(-10.0),
+                locked_changed: true,
+            }]
+        );
+        // a client missing from a state has no balance there
+        after
+            .process(tx(TransactionEnum::Deposit, 4, 4, dec!(1.5)))
+            .unwrap();
+        assert_eq!(after.diff(&before)[1].client, 4);
+        assert_eq!(after.diff(&before)[1].total, dec!(-1.5));
+    }
+
+    #[test]
+    fn held_breakdown() {
+        let mut engine = PaymentEngine::with_policy(Policy {
+            partial_hold: true,
+            ..Default::default()
+        });
+        for (tx_type, tx_id, tx_amount) in [
+            (TransactionEnum::Deposit, 1, dec!(10.0)),
+            (TransactionEnum::Deposit, 2, dec!(4.5)),
+            (TransactionEnum::Deposit, 3, dec!(7.0)),
+            (TransactionEnum::Dispute, 3, Decimal::ZERO),
+            (TransactionEnum::Dispute, 1, Decimal::ZERO),
+            (TransactionEnum::Withdrawal, 4, dec!(1.0)),
+            // only the remaining 3.5 are held
+            (TransactionEnum::Dispute, 2, Decimal::ZERO),
+            (TransactionEnum::Resolve, 3, Decimal::ZERO),
+        ] {
+            engine.process(tx(tx_type, 1, tx_id, tx_amount)).unwrap();
+        }
+
+        let breakdown = engine.held_breakdown(1);
+        assert_eq!(breakdown, vec![(1, dec!(10.0)), (2, dec!(3.5))]);
+        assert_eq!(
+            breakdown.iter().map(|(_, held)| held).sum::<Decimal>(),
+            engine.client(1).unwrap().held()
+        );
+        assert!(engine.held_breakdown(2).is_empty());
+    }
+
+    #[test]
+    fn reset() {
+        let mut engine = PaymentEngine::with_policy(Policy {
+            unique_tx_ids: true,
+            ordered_tx_ids: Some(TxOrder::Global),
+            ..Default::default()
+        });
+        let process_file = |engine: &mut PaymentEngine, path| {
+            let file = File::open(path).unwrap();
+            let mut input = Input::csv(InputConfig::default().from_reader(file));
+            while let Some(tx) = input.read_tx().unwrap() {
+                let _ = engine.process(tx);
+            }
+            engine.accounts()
+        };
+
+        assert_eq!(
+            process_file(&mut engine, "csv_files/balance_test.csv").len(),
+            2
+        );
+        engine.reset();
+        // the second file doesn't see the clients and transaction IDs of the first
+        let accounts = process_file(&mut engine, "csv_files/tx_test.csv");
+        assert_eq!(
+            accounts
+                .iter()
+                .map(|account| (account.client, account.total))
+                .collect::<Vec<_>>(),
+            vec![(1, dec!(7.0))]
+        );
+        assert!(engine.dispute_history(2).is_empty());
+        engine.reconcile(dec!(7.0)).unwrap();
+    }
+
+    #[test]
+    fn system_totals() {
+        assert_eq!(
+            PaymentEngine::new().system_totals(),
+            SystemTotals::default()
+        );
+
+        let mut engine = PaymentEngine::new();
+        let file = File::open("csv_files/balance_test.csv").unwrap();
+        let mut input = Input::csv(InputConfig::default().from_reader(file));
+        while let Some(tx) = input.read_tx().unwrap() {
+            let _ = engine.process(tx);
+        }
+        // client 1 has 17 available, client 2 has 9 available and 100 held
+        assert_eq!(
+            engine.system_totals(),
+            SystemTotals {
+                total_available: dec!(26.0),
+                total_held: dec!(100.0),
+                total: dec!(126.0),
+            }
+        );
+        assert_eq!(
+            engine.system_totals().to_string(),
+            "available: 26.0000, held: 100.0000, total: 126.0000"
+        );
+    }
+
+    #[test]
+    fn group_totals() {
+        let tagged = |tx_type, client_id, tx_id, tx_amount, group_id| Transaction {
+            group_id,
+            ..tx(tx_type, client_id, tx_id, tx_amount)
+        };
+        let mut engine = PaymentEngine::new();
+        let steps = [
+            tagged(TransactionEnum::Deposit, 1, 1, dec!(10.0), Some(7)),
+            tagged(TransactionEnum::Deposit, 2, 2, dec!(2.5), Some(7)),
+            tagged(TransactionEnum::Deposit, 3, 3, dec!(4.0), Some(9)),
+            // the client keeps its group without repeating the tag
+            tagged(TransactionEnum::Withdrawal, 1, 4, dec!(1.0), None),
+            // untagged clients aren't aggregated
+            tagged(TransactionEnum::Deposit, 4, 5, dec!(100.0), None),
+            // a rejected transaction doesn't tag its client
+            tagged(TransactionEnum::Withdrawal, 4, 6, dec!(500.0), Some(9)),
+        ];
+        for tx in steps {
+            let _ = engine.process(tx);
+        }
+
+        assert_eq!(
+            engine.group_totals().into_iter().collect::<Vec<_>>(),
+            vec![(7, dec!(11.5)), (9, dec!(4.0))]
+        );
+        assert_eq!(engine.client(1).unwrap().group(), Some(7));
+        assert_eq!(engine.client(4).unwrap().group(), None);
+    }
+
+    #[test]
+    fn transfer() {
+        let transfer = |client_id, tx_id, tx_amount, destination_id| Transaction {
+            destination_id,
+            ..tx(TransactionEnum::Transfer, client_id, tx_id, tx_amount)
+        };
+        let balances = |engine: &PaymentEngine, client_id| {
+            engine
+                .client(client_id)
+                .map(|client| (client.available(), client.total()))
+        };
+        let mut engine = PaymentEngine::new();
+        engine
+            .process(tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)))
+            .unwrap();
+        engine.process(transfer(1, 2, dec!(4.0), Some(2))).unwrap();
+        assert_eq!(balances(&engine, 1), Some((dec!(6.0), dec!(6.0))));
+        assert_eq!(balances(&engine, 2), Some((dec!(4.0), dec!(4.0))));
+
+        // a rejected transfer leaves both accounts unchanged
+        let err = engine
+            .process(transfer(1, 3, dec!(7.0), Some(2)))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Not enough available balance to process withdrawal!"));
+        assert!(engine.process(transfer(1, 4, dec!(1.0), None)).is_err());
+        assert!(engine.process(transfer(1, 5, dec!(1.0), Some(1))).is_err());
+        for amount in [dec!(-1.0), Decimal::ZERO] {
+            let err = engine.process(transfer(1, 7, amount, Some(3))).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Transfer ID: 7 has an amount of {amount}, it must be positive!")
+            );
+        }
+        assert!(engine.client(3).is_none());
+        assert_eq!(balances(&engine, 1), Some((dec!(6.0), dec!(6.0))));
+        assert_eq!(balances(&engine, 2), Some((dec!(4.0), dec!(4.0))));
+
+        // a frozen destination is checked before the source is withdrawn
+        engine
+            .process(tx(TransactionEnum::Dispute, 2, 2, Decimal::ZERO))
+            .unwrap();
+        engine
+            .process(tx(TransactionEnum::Chargeback, 2, 2, Decimal::ZERO))
+            .unwrap();
+        let err = engine
+            .process(transfer(1, 6, dec!(1.0), Some(2)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Account is currently frozen, transaction ID: 6 was not processed!"
+        );
+        assert_eq!(balances(&engine, 1), Some((dec!(6.0), dec!(6.0))));
+        engine.reconcile(dec!(6.0)).unwrap();
+    }
+
+    #[test]
+    fn reconcile() {
+        let mut engine = PaymentEngine::with_policy(Policy {
+            withdrawal_disputes: WithdrawalDispute::Credit,
+            ..Default::default()
+        });
+        let steps = [
+            tx(TransactionEnum::Deposit, 1, 1, dec!(10.0)),
+            tx(TransactionEnum::Deposit, 1, 2, dec!(5.5)),
+            tx(TransactionEnum::Withdrawal, 1, 3, dec!(4.0)),
+            tx(TransactionEnum::Deposit, 2, 4, dec!(0.00007)),
+            tx(TransactionEnum::Dispute, 1, 1, Decimal::ZERO),
+            tx(TransactionEnum::Chargeback, 1, 1, Decimal::ZERO),
+            tx(TransactionEnum::Reversal, 1, 1, Decimal::ZERO),
+            tx(TransactionEnum::Dispute, 1, 3, Decimal::ZERO),
+            tx(TransactionEnum::Chargeback, 1, 3, Decimal::ZERO),
+            tx(TransactionEnum::Reversal, 1, 3, Decimal::ZERO),
+            tx(TransactionEnum::Withdrawal, 1, 6, dec!(1.0)),
+            tx(TransactionEnum::Dispute, 1, 6, Decimal::ZERO),
+            tx(TransactionEnum::Resolve, 1, 6, Decimal::ZERO),
+            tx(TransactionEnum::Dispute, 2, 4, Decimal::ZERO),
+            tx(TransactionEnum::Chargeback, 2, 4, Decimal::ZERO),
+            // rejected, the account is frozen
+            tx(TransactionEnum::Deposit, 2, 5, dec!(1.0)),
+        ];
+        let mut expected = Decimal::ZERO;
+        for tx in steps {
+            let change = engine.total_change(&tx);
+            if engine.process(tx).is_ok() {
+                expected += change;
+            }
+        }
+        engine.reconcile(expected).unwrap();
+        assert_eq!(expected, dec!(10.5));
+
+        // a drift between the accounts and the applied transactions is reported
+        let err = engine.reconcile(expected + dec!(0.0001)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Reconciliation failed! The total balances sum to 10.5 instead of 10.5001, a difference of -0.0001"
+        );
+    }
+}
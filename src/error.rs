@@ -0,0 +1,58 @@
+use std::fmt;
+
+/// Errors callers may want to tell apart, they are returned wrapped in an
+/// [`anyhow::Error`] and can be recovered with `downcast_ref::<EngineError>()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineError {
+    /// A record has a number of fields that doesn't match any transaction
+    RecordLength {
+        /// Number of fields of the record
+        got: usize,
+        /// Line of the record
+        line: usize,
+    },
+    /// A record couldn't be parsed into a transaction
+    Malformed {
+        /// Line of the record
+        line: usize,
+        /// Reason the record is malformed
+        message: String,
+    },
+    /// A field read into the transaction isn't valid UTF-8
+    InvalidUtf8 {
+        /// Header of the field
+        field: String,
+        /// Line of the record
+        line: usize,
+    },
+}
+
+impl EngineError {
+    /// Returns the line of the record the error is about
+    pub fn line(&self) -> usize {
+        match self {
+            EngineError::RecordLength { line, .. }
+            | EngineError::Malformed { line, .. }
+            | EngineError::InvalidUtf8 { line, .. } => *line,
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::RecordLength { got, line } => {
+                write!(
+                    f,
+                    "line {line}: Error reading data, invalid length of {got}."
+                )
+            }
+            EngineError::Malformed { line, message } => write!(f, "line {line}: {message}"),
+            EngineError::InvalidUtf8 { field, line } => {
+                write!(f, "line {line}: invalid UTF-8 in field {field}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
@@ -0,0 +1,101 @@
+use anyhow::Error;
+use serde::Serialize;
+use std::io::{self, Write};
+
+use crate::error::EngineError;
+
+/// Format of the warnings and errors reported while processing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Free text through the logger
+    #[default]
+    Text,
+    /// One JSON object per line written to stderr
+    Json,
+}
+
+/// Structured report of a record that couldn't be applied, e.g.
+/// `{"line":null,"client":1,"tx":3,"kind":"rejected","message":"..."}`
+#[derive(Debug, Serialize, PartialEq)]
+pub(crate) struct Event {
+    /// Line of the record, when it's known
+    line: Option<usize>,
+    /// Client of the transaction, when it was parsed
+    client: Option<u16>,
+    /// ID of the transaction, when it was parsed
+    tx: Option<u32>,
+    /// `rejected` for a transaction that failed to be applied, `malformed` for
+    /// a record that couldn't be parsed
+    kind: &'static str,
+    /// Error message
+    message: String,
+}
+
+impl Event {
+    /// Reports a transaction that failed to be applied
+    pub(crate) fn rejected(client_id: u16, tx_id: u32, err: &Error) -> Self {
+        Self {
+            line: None,
+            client: Some(client_id),
+            tx: Some(tx_id),
+            kind: "rejected",
+            message: err.to_string(),
+        }
+    }
+
+    /// Reports a record that couldn't be parsed
+    pub(crate) fn malformed(err: &Error) -> Self {
+        Self {
+            line: err.downcast_ref::<EngineError>().map(EngineError::line),
+            client: None,
+            tx: None,
+            kind: "malformed",
+            message: err.to_string(),
+        }
+    }
+
+    /// Writes the event as a JSON line to stderr
+    pub(crate) fn emit(&self) {
+        let mut stderr = io::stderr().lock();
+        if serde_json::to_writer(&mut stderr, self).is_ok() {
+            let _ = writeln!(stderr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Event;
+    use crate::{config::InputConfig, input::Input};
+    use serde_json::json;
+
+    #[test]
+    fn json_shape() {
+        let err = anyhow::anyhow!("cannot dispute tx 3: no such transaction");
+        assert_eq!(
+            serde_json::to_value(Event::rejected(1, 3, &err)).unwrap(),
+            json!({
+                "line": null,
+                "client": 1,
+                "tx": 3,
+                "kind": "rejected",
+                "message": "cannot dispute tx 3: no such transaction",
+            })
+        );
+
+        let data = "type,client,tx,amount\ndeposit,1,1,abc\n";
+        let err = Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+            .read_tx()
+            .unwrap_err();
+        assert_eq!(
+            serde_json::to_value(Event::malformed(&err)).unwrap(),
+            json!({
+                "line": 2,
+                "client": null,
+                "tx": null,
+                "kind": "malformed",
+                "message": "line 2: could not parse amount 'abc'",
+            })
+        );
+    }
+}
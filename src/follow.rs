@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Context, Result};
+use std::{
+    io::{self, Read},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tokio::sync::mpsc::{self, Receiver};
+
+use crate::{config::Config, transaction::Transaction};
+
+/// Interval at which the end of a followed file is polled for appended bytes
+const POLL: Duration = Duration::from_millis(50);
+
+/// Number of transactions the reader can parse ahead of the processing
+const READ_AHEAD: usize = 1024;
+
+/// Reader of a file that is still being appended to, like `tail -f`. At the
+/// end of the file it waits for more bytes instead of ending, so a partially
+/// written line is only parsed once it's complete
+struct Follow<R> {
+    file: R,
+    /// the file ends once it's set
+    stop: Arc<AtomicBool>,
+}
+
+impl<R: Read> Read for Follow<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            // the bytes appended before stopping are still read
+            if self.stop.load(Ordering::Acquire) {
+                return self.file.read(buf);
+            }
+            thread::sleep(POLL);
+        }
+    }
+}
+
+/// Transactions of a followed file parsed by a reader thread
+pub(crate) struct FollowedFile {
+    /// transactions in the order they are appended
+    pub(crate) rx: Receiver<Transaction>,
+    /// resolves to the number of malformed records skipped
+    handle: JoinHandle<Result<u64>>,
+}
+
+impl FollowedFile {
+    /// Spawns the thread reading the file until `stop` is set, the file is
+    /// opened and its lines limited like any other input
+    pub(crate) fn spawn(path: &Path, config: &Config, stop: Arc<AtomicBool>) -> Result<Self> {
+        let file = crate::open(path, config)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+        let config = config.clone();
+        let (tx, rx) = mpsc::channel(READ_AHEAD);
+        let handle = thread::spawn(move || {
+            let reader = crate::limit(Follow { file, stop }, &config);
+            let mut input = crate::configure(crate::parse(reader, &config), &config);
+            input.skip_records(config.skip)?;
+            while let Some(transaction) = input.read_tx()? {
+                // the processing was stopped
+                if tx.blocking_send(transaction).is_err() {
+                    break;
+                }
+            }
+            Ok(input.skipped())
+        });
+        Ok(Self { rx, handle })
+    }
+
+    /// Waits for the reader thread once every transaction was received,
+    /// returns the number of malformed records skipped
+    pub(crate) async fn finish(self) -> Result<u64> {
+        drop(self.rx);
+        // the reader may still be polling the end of the file
+        tokio::task::spawn_blocking(move || self.handle.join())
+            .await?
+            .map_err(|_| anyhow!("The file reader stopped unexpectedly!"))?
+    }
+}
@@ -0,0 +1,118 @@
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use std::fmt;
+
+use crate::account::format_amount;
+
+/// Range of balances and the number of accounts in it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bin {
+    /// Lower bound of the range, inclusive
+    pub lower: Decimal,
+    /// Upper bound of the range, exclusive except for the last bin
+    pub upper: Decimal,
+    /// Number of accounts in the range
+    pub count: usize,
+}
+
+/// Distribution of the account balances
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Bins ordered by ascending range
+    pub bins: Vec<Bin>,
+}
+
+impl Histogram {
+    /// Bins the balances in `bins` ranges of equal width, auto-scaled between
+    /// the smallest and largest balance
+    pub fn new(balances: &[Decimal], bins: usize) -> Self {
+        let (Some(min), Some(max)) = (balances.iter().min(), balances.iter().max()) else {
+            return Self { bins: vec![] };
+        };
+        if bins == 0 {
+            return Self { bins: vec![] };
+        }
+
+        // when every balance is the same a single bin holds all of them
+        let bins = if min == max { 1 } else { bins };
+        let width = (max - min) / Decimal::from(bins);
+        let mut histogram = Self {
+            bins: (0..bins)
+                .map(|bin| Bin {
+                    lower: min + width * Decimal::from(bin),
+                    upper: if bin + 1 == bins {
+                        *max
+                    } else {
+                        min + width * Decimal::from(bin + 1)
+                    },
+                    count: 0,
+                })
+                .collect(),
+        };
+
+        balances.iter().for_each(|balance| {
+            let bin = if width.is_zero() {
+                0
+            } else {
+                ((balance - min) / width)
+                    .floor()
+                    .to_usize()
+                    .unwrap_or_default()
+                    .min(bins - 1)
+            };
+            histogram.bins[bin].count += 1;
+        });
+        histogram
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for bin in &self.bins {
+            writeln!(
+                f,
+                "{} - {}: {}",
+                format_amount(bin.lower),
+                format_amount(bin.upper),
+                bin.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bin, Histogram};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn bin_counts() {
+        let histogram = Histogram::new(
+            &[
+                dec!(0),
+                dec!(1.5),
+                dec!(2),
+                dec!(4.9),
+                dec!(5),
+                dec!(7.5),
+                dec!(10),
+            ],
+            4,
+        );
+        let counts: Vec<usize> = histogram.bins.iter().map(|bin| bin.count).collect();
+        assert_eq!(counts, vec![3, 1, 1, 2]);
+        assert_eq!(histogram.bins[1].lower, dec!(2.5));
+        assert_eq!(histogram.bins[3].upper, dec!(10));
+
+        // the same balance everywhere ends up in a single bin
+        assert_eq!(
+            Histogram::new(&[dec!(3), dec!(3)], 4).bins,
+            vec![Bin {
+                lower: dec!(3),
+                upper: dec!(3),
+                count: 2,
+            }]
+        );
+        assert!(Histogram::new(&[], 4).bins.is_empty());
+    }
+}
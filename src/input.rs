@@ -0,0 +1,826 @@
+use anyhow::{bail, Result};
+use csv::{ByteRecord, Reader};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    path::PathBuf,
+};
+
+use crate::{
+    config::Config,
+    error::EngineError,
+    event::{Event, LogFormat},
+    merge::Merge,
+    transaction::{accounting_negative, Transaction},
+};
+
+lazy_static::lazy_static! {
+    // Deposits and Withdrawals have 4 inputs
+    pub(crate) static ref FOUR_INPUTS: ByteRecord = ByteRecord::from(
+        vec!["type", "client", "tx", "amount"]
+    );
+
+    // Disputes, Resolves, Chargebacks, and Reversals have 3 inputs
+    pub(crate) static ref THREE_INPUTS: ByteRecord = ByteRecord::from(
+        vec!["type", "client", "tx"]
+    );
+}
+
+/// Columns read into a transaction, the fields of the other ones are ignored
+const TX_COLUMNS: [&[u8]; 8] = [
+    b"type",
+    b"client",
+    b"tx",
+    b"amount",
+    b"timestamp",
+    b"idempotency_key",
+    b"destination",
+    b"group",
+];
+
+/// Format of the transactions file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// CSV records with a header
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+/// Where the transactions are parsed from
+enum Source<R> {
+    /// `headers` are the file's own headers when the records are mapped by
+    /// them, they are inspected before the first record
+    Csv {
+        reader: Reader<R>,
+        headers: Option<ByteRecord>,
+        inspected: bool,
+    },
+    /// `line` is the number of lines read so far
+    Ndjson { reader: BufReader<R>, line: u64 },
+    /// Transactions of several files read concurrently
+    Merge(Merge),
+}
+
+/// Reads transactions from a CSV or newline-delimited JSON input, the
+/// processing that follows doesn't depend on the format
+pub struct Input<R> {
+    /// sources read one after the other
+    sources: VecDeque<Source<R>>,
+    /// buffer reused for every CSV record
+    record: ByteRecord,
+    /// buffer reused for every JSON line
+    line: String,
+    /// abort on a malformed record instead of skipping it
+    strict: bool,
+    /// format of the skipped records reports
+    log_format: LogFormat,
+    /// derive deposits and withdrawals from the sign of their amount
+    signed_amounts: bool,
+    /// read the amounts as integer minor units
+    amounts_in_cents: bool,
+    /// maximum number of digits of the integer part of the amounts
+    max_integer_digits: Option<u32>,
+    /// read the amounts in parentheses as negatives
+    accounting_negatives: bool,
+    /// prefix of the lines skipped as comments
+    comment: Option<String>,
+    /// number of malformed records skipped
+    skipped: u64,
+    /// number of records read, the malformed ones included
+    records: u64,
+}
+
+impl<R: Read> Input<R> {
+    fn new(source: Source<R>) -> Self {
+        Self {
+            sources: VecDeque::from([source]),
+            record: ByteRecord::new(),
+            line: String::new(),
+            strict: true,
+            log_format: LogFormat::default(),
+            signed_amounts: false,
+            amounts_in_cents: false,
+            max_integer_digits: None,
+            accounting_negatives: false,
+            comment: None,
+            skipped: 0,
+            records: 0,
+        }
+    }
+
+    /// Returns an input parsing CSV records
+    pub fn csv(reader: Reader<R>) -> Self {
+        Self::new(Source::Csv {
+            reader,
+            headers: None,
+            inspected: false,
+        })
+    }
+
+    /// Returns an input parsing one JSON object per line, e.g.
+    /// `{"type":"deposit","client":1,"tx":1,"amount":"10.0"}`
+    pub fn ndjson(reader: R) -> Self {
+        Self::new(Source::Ndjson {
+            reader: BufReader::new(reader),
+            line: 0,
+        })
+    }
+
+    /// Returns an input merging the transactions of the files by timestamp,
+    /// the files are parsed concurrently
+    pub(crate) fn merge(paths: &[PathBuf], config: &Config) -> Self {
+        Self::new(Source::Merge(Merge::spawn(paths, config)))
+    }
+
+    /// Returns an input reading the transactions of `other` once this one is over
+    pub fn chain(mut self, other: Self) -> Self {
+        self.sources.extend(other.sources);
+        self
+    }
+
+    /// Sets whether a malformed record aborts the reading, defaults to `true`.
+    ///
+    /// When it's not strict malformed records are logged and skipped
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets the format of the skipped records reports, defaults to
+    /// [`LogFormat::Text`]
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    /// Sets whether a deposit or withdrawal is derived from the sign of its
+    /// amount instead of its type, defaults to `false`.
+    ///
+    /// A negative amount is a withdrawal and a positive one a deposit
+    pub fn signed_amounts(mut self, signed_amounts: bool) -> Self {
+        self.signed_amounts = signed_amounts;
+        self
+    }
+
+    /// Sets whether the amounts are integer minor units, e.g. `1050` for
+    /// `10.50`, defaults to `false`.
+    ///
+    /// An amount with a fractional part is a malformed record
+    pub fn amounts_in_cents(mut self, amounts_in_cents: bool) -> Self {
+        self.amounts_in_cents = amounts_in_cents;
+        self
+    }
+
+    /// Sets the maximum number of digits of the integer part of the amounts,
+    /// defaults to `None` which doesn't limit them.
+    ///
+    /// An amount exceeding it is a malformed record
+    pub fn max_integer_digits(mut self, max_integer_digits: Option<u32>) -> Self {
+        self.max_integer_digits = max_integer_digits;
+        self
+    }
+
+    /// Sets whether an amount in parentheses is negative, e.g. `(10.00)` is
+    /// `-10.00` as in accounting exports, defaults to `false`
+    pub fn accounting_negatives(mut self, accounting_negatives: bool) -> Self {
+        self.accounting_negatives = accounting_negatives;
+        self
+    }
+
+    /// Sets the prefix of the lines skipped as comments, e.g. `#`, defaults
+    /// to `None`.
+    ///
+    /// A CSV record is a comment when it's first field starts with the prefix
+    pub fn comment(mut self, prefix: Option<String>) -> Self {
+        self.comment = prefix;
+        self
+    }
+
+    /// Returns the number of malformed records skipped
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Returns the number of records read so far, the malformed ones included
+    pub(crate) fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Skips the next `count` records without parsing them, e.g. the ones a
+    /// previous run already applied, so a malformed one doesn't abort a strict
+    /// run. Comments and blank lines aren't records.
+    ///
+    /// Returns the number of records skipped, fewer once every source is over
+    pub(crate) fn skip_records(&mut self, count: u64) -> Result<u64> {
+        let mut skipped = 0;
+        while skipped < count {
+            let Some(source) = self.sources.front_mut() else {
+                break;
+            };
+            let comment = self.comment.as_deref();
+            if Self::skip_source(source, &mut self.record, &mut self.line, comment)? {
+                skipped += 1;
+            } else if let Some(Source::Merge(merge)) = self.sources.pop_front() {
+                self.skipped += merge.skipped;
+            }
+        }
+        self.records += skipped;
+        Ok(skipped)
+    }
+
+    /// Waits until the next record can be read without blocking, the files of
+    /// a merge are parsed by reader threads the async processing doesn't block on
+    pub(crate) async fn ready(&mut self) {
+        if let Some(Source::Merge(merge)) = self.sources.front_mut() {
+            merge.ready().await;
+        }
+    }
+
+    /// Reads the next transaction, returns `None` once every source is over
+    pub(crate) fn read_tx(&mut self) -> Result<Option<Transaction>> {
+        while let Some(source) = self.sources.front_mut() {
+            let comment = self.comment.as_deref();
+            let mut read = Self::read_source(
+                source,
+                &mut self.record,
+                &mut self.line,
+                comment,
+                self.accounting_negatives,
+            )?;
+            if self.amounts_in_cents {
+                read = read.map(|tx| tx.and_then(Transaction::cents_to_units));
+            }
+            if let Some(max) = self.max_integer_digits {
+                read = read.map(|tx| tx.and_then(|tx| tx.check_integer_digits(max)));
+            }
+            match read {
+                Some(Ok(mut tx)) => {
+                    if self.signed_amounts {
+                        tx.apply_sign();
+                    }
+                    self.records += 1;
+                    return Ok(Some(tx));
+                }
+                Some(Err(err)) if !self.strict => {
+                    match self.log_format {
+                        LogFormat::Text => log::warn!("Skipping malformed record, {err}"),
+                        LogFormat::Json => Event::malformed(&err).emit(),
+                    }
+                    self.skipped += 1;
+                    self.records += 1;
+                }
+                Some(Err(err)) => return Err(err),
+                None => {
+                    if let Some(Source::Merge(merge)) = self.sources.pop_front() {
+                        self.skipped += merge.skipped;
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the next record of a single source, the inner error reports a
+    /// malformed record and it's line while the outer one a failure of the reader
+    fn read_source(
+        source: &mut Source<R>,
+        record: &mut ByteRecord,
+        buf: &mut String,
+        comment: Option<&str>,
+        accounting_negatives: bool,
+    ) -> Result<Option<Result<Transaction>>> {
+        let commented =
+            |line: &[u8]| comment.is_some_and(|prefix| line.starts_with(prefix.as_bytes()));
+        match source {
+            Source::Csv {
+                reader,
+                headers,
+                inspected,
+            } => {
+                // a file with other columns than the known four, e.g. a timestamp
+                // or a memo, is mapped by it's headers and the unknown columns
+                // are ignored
+                if !*inspected {
+                    *inspected = true;
+                    if reader.has_headers() {
+                        // the names are matched trimmed whatever the trim mode
+                        // of the fields
+                        let mut file_headers = reader.byte_headers()?.clone();
+                        file_headers.trim();
+                        if file_headers != *FOUR_INPUTS {
+                            *headers = Some(file_headers);
+                        }
+                    }
+                }
+                let line = loop {
+                    let read = reader.read_byte_record(record);
+                    let line = reader.position().line();
+                    match read {
+                        Ok(false) => return Ok(None),
+                        Err(err) if err.is_io_error() => bail!(err),
+                        Err(err) => return Ok(Some(Err(malformed(line, err)))),
+                        Ok(true) => {}
+                    }
+                    if !record.get(0).is_some_and(commented) {
+                        break line;
+                    }
+                };
+                let line = record.position().map_or(line, |pos| pos.line());
+                // for every record we must ensure it has the right amount of inputs on the line
+                let short_headers: ByteRecord;
+                let headers: &ByteRecord = match (headers.as_ref(), record.len()) {
+                    // the trailing columns may be left out, e.g. by a dispute
+                    (Some(headers), len) if len < headers.len() => {
+                        short_headers = headers.iter().take(len).collect();
+                        &short_headers
+                    }
+                    (Some(headers), _) => headers,
+                    (None, 3) => &THREE_INPUTS,
+                    (None, 4) => &FOUR_INPUTS,
+                    _ => {
+                        return Ok(Some(Err(EngineError::RecordLength {
+                            got: record.len(),
+                            line: line as usize,
+                        }
+                        .into())))
+                    }
+                };
+                if accounting_negatives {
+                    negate_amount(headers, record);
+                }
+                // the deserializer would only report an opaque error
+                let invalid = headers.iter().zip(record.iter()).find(|(header, field)| {
+                    TX_COLUMNS.contains(header) && std::str::from_utf8(field).is_err()
+                });
+                if let Some((header, _)) = invalid {
+                    return Ok(Some(Err(EngineError::InvalidUtf8 {
+                        field: String::from_utf8_lossy(header).into_owned(),
+                        line: line as usize,
+                    }
+                    .into())));
+                }
+                Ok(Some(record.deserialize(Some(headers)).map_err(|err| {
+                    match err.kind() {
+                        // the field's value is part of the message
+                        csv::ErrorKind::Deserialize { err, .. } => malformed(line, err.kind()),
+                        _ => malformed(line, err),
+                    }
+                })))
+            }
+            Source::Ndjson { reader, line } => loop {
+                buf.clear();
+                if reader.read_line(buf)? == 0 {
+                    return Ok(None);
+                }
+                *line += 1;
+                // blank lines and comments don't hold a transaction
+                let trimmed = buf.trim();
+                if !trimmed.is_empty() && !commented(trimmed.as_bytes()) {
+                    let line = *line;
+                    return Ok(Some(parse_json(buf, accounting_negatives).map_err(|err| {
+                        malformed(line, format!("Error reading data, {}, {err}", buf.trim()))
+                    })));
+                }
+            },
+            Source::Merge(merge) => Ok(merge.next_tx()?.map(Ok)),
+        }
+    }
+
+    /// Skips the next record of a single source without parsing it, returns
+    /// `false` once the source is over
+    fn skip_source(
+        source: &mut Source<R>,
+        record: &mut ByteRecord,
+        buf: &mut String,
+        comment: Option<&str>,
+    ) -> Result<bool> {
+        let commented =
+            |line: &[u8]| comment.is_some_and(|prefix| line.starts_with(prefix.as_bytes()));
+        match source {
+            Source::Csv { reader, .. } => loop {
+                match reader.read_byte_record(record) {
+                    Ok(false) => return Ok(false),
+                    Err(err) if err.is_io_error() => bail!(err),
+                    // even a record the parser can't split is skipped
+                    Err(_) => return Ok(true),
+                    Ok(true) if record.get(0).is_some_and(commented) => {}
+                    Ok(true) => return Ok(true),
+                }
+            },
+            Source::Ndjson { reader, line } => loop {
+                buf.clear();
+                if reader.read_line(buf)? == 0 {
+                    return Ok(false);
+                }
+                *line += 1;
+                let trimmed = buf.trim();
+                if !trimmed.is_empty() && !commented(trimmed.as_bytes()) {
+                    return Ok(true);
+                }
+            },
+            // the records of merged files are parsed by their reader threads
+            Source::Merge(merge) => Ok(merge.next_tx()?.is_some()),
+        }
+    }
+}
+
+/// Returns the error of a malformed record
+fn malformed(line: u64, message: impl fmt::Display) -> anyhow::Error {
+    EngineError::Malformed {
+        line: line as usize,
+        message: message.to_string(),
+    }
+    .into()
+}
+
+/// Reader rejecting lines longer than a maximum size, it guards against
+/// enormous fields being buffered in memory by the parsers
+pub struct LimitReader<R> {
+    inner: R,
+    /// maximum number of bytes of a line, the newline excluded
+    limit: usize,
+    /// bytes read since the last newline
+    line_len: usize,
+}
+
+impl<R: Read> LimitReader<R> {
+    /// Returns a reader failing once a line exceeds `limit` bytes
+    pub fn new(inner: R, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            line_len: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        for byte in &buf[..len] {
+            if *byte == b'\n' {
+                self.line_len = 0;
+            } else {
+                self.line_len += 1;
+            }
+            if self.line_len > self.limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("record exceeds the maximum size of {} bytes", self.limit),
+                ));
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// Parses a JSON transaction, numeric amounts are read through their textual
+/// form like the quoted ones
+fn parse_json(line: &str, accounting_negatives: bool) -> Result<Transaction> {
+    let mut value: Value = serde_json::from_str(line)?;
+    if let Some(amount) = value.get_mut("amount") {
+        match amount {
+            Value::Number(number) => *amount = Value::String(number.to_string()),
+            Value::String(text) if accounting_negatives => {
+                if let Some(negative) = accounting_negative(text) {
+                    *text = negative;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Transaction::deserialize(value)?)
+}
+
+/// Rewrites the amount of a CSV record written in parentheses as a negative
+fn negate_amount(headers: &ByteRecord, record: &mut ByteRecord) {
+    let Some(index) = headers.iter().position(|header| header == b"amount") else {
+        return;
+    };
+    let negative = record
+        .get(index)
+        .and_then(|amount| std::str::from_utf8(amount).ok())
+        .and_then(accounting_negative);
+    if let Some(negative) = negative {
+        *record = record
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                if i == index {
+                    negative.as_bytes()
+                } else {
+                    field
+                }
+            })
+            .collect();
+    }
+}
+
+impl<R: Read> From<Reader<R>> for Input<R> {
+    fn from(reader: Reader<R>) -> Self {
+        Self::csv(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Input, LimitReader};
+    use crate::account::format_amount;
+    use crate::error::EngineError;
+    use crate::{
+        config::InputConfig,
+        transaction::{Transaction, TransactionEnum},
+    };
+    use csv::Trim;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn accounting_negatives() {
+        let data = "type,client,tx,amount
+deposit,1,1,(10.00)
+deposit,1,2,\" ( 1,000.5 )\"
+withdrawal,1,3,2.5
+";
+        let mut input = Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+            .accounting_negatives(true);
+        let mut amounts = vec![];
+        while let Some(tx) = input.read_tx().unwrap() {
+            amounts.push(tx.tx_amount);
+        }
+        assert_eq!(amounts, vec![dec!(-10.00), dec!(-1000.5), dec!(2.5)]);
+        assert_eq!(format_amount(amounts[0]), "-10.0000");
+
+        let data = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"(10.00)\"}\n";
+        let mut input = Input::ndjson(data.as_bytes()).accounting_negatives(true);
+        assert_eq!(input.read_tx().unwrap().unwrap().tx_amount, dec!(-10.00));
+        // without the option they are malformed
+        let mut input = Input::ndjson(data.as_bytes());
+        assert!(input.read_tx().is_err());
+    }
+
+    #[test]
+    fn max_integer_digits() {
+        let data = "type,client,tx,amount
+deposit,1,1,999999.9999
+deposit,1,2,1000000
+withdrawal,1,3,-1234567.5
+deposit,1,4,12345678901234567890123456789012345678901234567890
+deposit,1,5,0.5
+";
+        let mut input = Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+            .max_integer_digits(Some(6))
+            .strict(false);
+        let mut tx_ids = vec![];
+        while let Some(tx) = input.read_tx().unwrap() {
+            tx_ids.push(tx.tx_id);
+        }
+        assert_eq!(tx_ids, vec![1, 5]);
+        assert_eq!(input.skipped(), 3);
+
+        let mut input = Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+            .max_integer_digits(Some(6));
+        input.read_tx().unwrap();
+        assert_eq!(
+            input.read_tx().unwrap_err().to_string(),
+            "Transaction ID: 2 amount 1000000 has more than 6 integer digits!"
+        );
+    }
+
+    #[test]
+    fn comment_lines() {
+        let data = "type,client,tx,amount
+# opening balance
+deposit,1,1,10.0
+  #withdrawal,1,2,5.0
+withdrawal,1,3,2.0
+";
+        let read = |comment: Option<&str>| {
+            let mut input = Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+                .comment(comment.map(String::from))
+                .strict(false);
+            let mut tx_ids = vec![];
+            while let Some(tx) = input.read_tx().unwrap() {
+                tx_ids.push(tx.tx_id);
+            }
+            (tx_ids, input.skipped())
+        };
+        assert_eq!(read(Some("#")), (vec![1, 3], 0));
+        // without a prefix the comments are malformed records
+        assert_eq!(read(None), (vec![1, 3], 2));
+
+        let data = "# opening balance\n{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1}\n";
+        let mut input = Input::ndjson(data.as_bytes()).comment(Some("#".to_string()));
+        assert_eq!(input.read_tx().unwrap().unwrap().tx_id, 1);
+        assert!(input.read_tx().unwrap().is_none());
+    }
+
+    #[test]
+    fn skip_records() {
+        let data = "type,client,tx,amount
+# opening balance
+deposit,1,1,abc
+deposit,1,2,5.0
+deposit,1,3,1.0
+";
+        let mut input = Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+            .comment(Some("#".to_string()));
+        // the comment isn't a record, the malformed one isn't parsed
+        assert_eq!(input.skip_records(2).unwrap(), 2);
+        assert_eq!(input.read_tx().unwrap().unwrap().tx_id, 3);
+        assert_eq!(input.records(), 3);
+        assert_eq!(input.skip_records(5).unwrap(), 0);
+
+        let data = "{\"type\"\n\n{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":1}\n";
+        let mut input = Input::ndjson(data.as_bytes());
+        assert_eq!(input.skip_records(1).unwrap(), 1);
+        assert_eq!(input.read_tx().unwrap().unwrap().tx_id, 2);
+    }
+
+    #[test]
+    fn read_ndjson() {
+        let data = r#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}
+
+{"type":"withdrawal","client":1,"tx":2,"amount":2}
+{"type":"dispute","client":1,"tx":1}
+"#;
+        let mut input = Input::ndjson(data.as_bytes());
+        let mut txs = vec![];
+        while let Some(tx) = input.read_tx().unwrap() {
+            txs.push(tx);
+        }
+
+        assert_eq!(
+            txs,
+            vec![
+                Transaction {
+                    tx_type: TransactionEnum::Deposit,
+                    client_id: 1,
+                    tx_id: 1,
+                    tx_amount: dec!(10.5),
+                    timestamp: None,
+                    idempotency_key: None,
+                    destination_id: None,
+                    group_id: None,
+                },
+                Transaction {
+                    tx_type: TransactionEnum::Withdrawal,
+                    client_id: 1,
+                    tx_id: 2,
+                    tx_amount: dec!(2),
+                    timestamp: None,
+                    idempotency_key: None,
+                    destination_id: None,
+                    group_id: None,
+                },
+                Transaction {
+                    tx_type: TransactionEnum::Dispute,
+                    client_id: 1,
+                    tx_id: 1,
+                    tx_amount: Decimal::ZERO,
+                    timestamp: None,
+                    idempotency_key: None,
+                    destination_id: None,
+                    group_id: None,
+                },
+            ]
+        );
+        assert!(Input::ndjson(r#"{"type":"deposit""#.as_bytes())
+            .read_tx()
+            .is_err());
+    }
+
+    #[test]
+    fn record_size_limit() {
+        let data = format!(
+            "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,10.0{}\n",
+            " ".repeat(64)
+        );
+        let mut input =
+            Input::csv(InputConfig::default().from_reader(LimitReader::new(data.as_bytes(), 32)));
+        let err = loop {
+            match input.read_tx() {
+                Ok(Some(_)) => continue,
+                Ok(None) => panic!("the oversized record was accepted"),
+                Err(err) => break err,
+            }
+        };
+        assert!(err
+            .to_string()
+            .contains("record exceeds the maximum size of 32 bytes"));
+
+        // records within the limit are read as usual
+        let mut input =
+            Input::csv(InputConfig::default().from_reader(LimitReader::new(data.as_bytes(), 128)));
+        let mut txs = 0;
+        while input.read_tx().unwrap().is_some() {
+            txs += 1;
+        }
+        assert_eq!(txs, 2);
+    }
+
+    #[test]
+    fn malformed_amount() {
+        let data = "type,client,tx,amount\ndeposit,1,1,abc\ndeposit,1,2,5.0\n";
+        let input = || Input::csv(InputConfig::default().from_reader(data.as_bytes()));
+
+        let err = input().read_tx().unwrap_err();
+        assert_eq!(err.to_string(), "line 2: could not parse amount 'abc'");
+
+        // without strict mode the malformed record is skipped
+        let mut input = input().strict(false);
+        assert_eq!(input.read_tx().unwrap().unwrap().tx_id, 2);
+        assert!(input.read_tx().unwrap().is_none());
+        assert_eq!(input.skipped(), 1);
+
+        let mut input = Input::ndjson(
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"abc\"}\n".as_bytes(),
+        );
+        assert!(input
+            .read_tx()
+            .unwrap_err()
+            .to_string()
+            .starts_with("line 1: "));
+    }
+
+    #[test]
+    fn unknown_columns() {
+        let data = "type,client,tx,amount,memo
+deposit,1,1,10.0,salary
+dispute,1,1,,\"customer, called\"
+resolve,1,1
+";
+        let mut input = Input::csv(InputConfig::default().from_reader(data.as_bytes()));
+        let mut txs = vec![];
+        while let Some(tx) = input.read_tx().unwrap() {
+            txs.push((tx.tx_type, tx.tx_amount));
+        }
+
+        assert_eq!(
+            txs,
+            vec![
+                (TransactionEnum::Deposit, dec!(10.0)),
+                (TransactionEnum::Dispute, Decimal::ZERO),
+                (TransactionEnum::Resolve, Decimal::ZERO),
+            ]
+        );
+    }
+
+    #[test]
+    fn untrimmed_headers() {
+        let data = "type, client, tx, amount, memo
+deposit,1,1,10.0, salary
+";
+        for trim in [Trim::Fields, Trim::None] {
+            let mut input = Input::csv(
+                InputConfig::builder()
+                    .trim(trim)
+                    .build()
+                    .from_reader(data.as_bytes()),
+            );
+            let tx = input.read_tx().unwrap().unwrap();
+            assert_eq!((tx.client_id, tx.tx_amount), (1, dec!(10.0)));
+        }
+    }
+
+    #[test]
+    fn record_length_error() {
+        let data = "deposit,1,1,10.0\ndeposit,1,2,10.0,5.0,1\n";
+        let mut input = Input::csv(
+            InputConfig::builder()
+                .has_headers(false)
+                .build()
+                .from_reader(data.as_bytes()),
+        );
+        input.read_tx().unwrap();
+
+        let err = input.read_tx().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<EngineError>(),
+            Some(&EngineError::RecordLength { got: 6, line: 2 })
+        );
+        assert_eq!(
+            err.to_string(),
+            "line 2: Error reading data, invalid length of 6."
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let data = b"type,client,tx,amount,memo\ndepos\xffit,1,1,10.0\ndeposit,1,2,5.0,\xff\n";
+        let err = Input::csv(InputConfig::default().from_reader(&data[..]))
+            .read_tx()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid UTF-8 in field type");
+
+        // the ignored columns aren't checked
+        let mut input = Input::csv(InputConfig::default().from_reader(&data[..])).strict(false);
+        assert_eq!(input.read_tx().unwrap().unwrap().tx_id, 2);
+        assert_eq!(input.skipped(), 1);
+    }
+}
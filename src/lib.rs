@@ -1,137 +1,682 @@
+mod account;
+mod archive;
+mod check;
+mod checkpoint;
 mod client;
+mod config;
+mod engine;
+mod error;
+mod event;
+mod follow;
+mod histogram;
+mod input;
+mod merge;
+mod output;
+mod policy;
 mod process;
 mod transaction;
 
-use crate::{process::ProcessTransactions, transaction::Transaction};
+pub use crate::{
+    account::{Account, AccountDiff, SystemTotals},
+    check::FileStats,
+    client::{Client, DisputeEvent},
+    config::{Config, InputConfig, InputConfigBuilder, SortBy},
+    engine::PaymentEngine,
+    error::EngineError,
+    event::LogFormat,
+    histogram::{Bin, Histogram},
+    input::{Input, InputFormat, LimitReader},
+    output::{buffered_stdout, decode_accounts, Column, OutputFormat, SharedWriter},
+    policy::{Policy, Precision, TxOrder, WithdrawalDispute},
+    process::{RunOutcome, RunReport},
+    transaction::{Transaction, TransactionEnum},
+};
+use crate::{
+    checkpoint::Checkpoint,
+    follow::FollowedFile,
+    process::{Command, ProcessTransactions},
+};
 
 use anyhow::{bail, Context, Result};
-use csv::{ByteRecord, Reader, ReaderBuilder, Trim, Writer};
-use std::fs::File;
-
-lazy_static::lazy_static! {
-    // Deposits and Withdrawals have 4 inputs
-    pub(crate) static ref FOUR_INPUTS: ByteRecord = ByteRecord::from(
-        vec!["type", "client", "tx", "amount"]
-    );
-
-    // Disputes, Resolves, and Chargebacks have 3 inputs
-    pub(crate) static ref THREE_INPUTS: ByteRecord = ByteRecord::from(
-        vec!["type", "client", "tx"]
-    );
-}
-
-/// Opens file name read from command line.
-/// Returns CSV parser
-pub fn initialize() -> Result<Reader<File>> {
-    match File::open(
-        std::env::args()
-            .nth(1)
-            .context("Unable to get arguments, file.csv expected as argument")?,
-    ) {
-        Ok(file) => {
-            return Ok(ReaderBuilder::new()
-                .delimiter(b',')
-                .flexible(true)
-                .trim(Trim::All)
-                .from_reader(file))
+use memmap2::Mmap;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Cursor, Read, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tokio::runtime::{Builder, Runtime};
+
+/// Builds the runtime running the processing, when the multi-threaded runtime
+/// can't be started (e.g. the thread limit of a container) it falls back to a
+/// single-threaded one
+pub fn runtime() -> Result<Runtime> {
+    build_runtime(|| Builder::new_multi_thread().enable_all().build())
+}
+
+/// Builds the runtime returned by `multi_thread` or the fallback one
+fn build_runtime(multi_thread: impl FnOnce() -> io::Result<Runtime>) -> Result<Runtime> {
+    match multi_thread() {
+        Ok(runtime) => Ok(runtime),
+        Err(err) => {
+            log::warn!(
+                "Unable to start the multi-threaded runtime, running on a single thread! {err}"
+            );
+            Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .context("Unable to start the async runtime")
         }
+    }
+}
+
+/// Opens the files read from command line.
+/// Returns the transactions parser for the configured input format, several
+/// files are parsed concurrently and merged by the timestamp of their
+/// transactions, without timestamps they are read one after the other
+pub fn initialize(config: &Config) -> Result<Input<Box<dyn Read>>> {
+    match config.inputs.as_slice() {
+        [] => bail!("Unable to get arguments, file.csv expected as argument"),
+        [path] => initialize_file(path, config),
+        _ => Ok(Input::merge(&config.inputs, config)),
+    }
+}
+
+/// Opens a single file.
+/// Returns the transactions parser for the configured input format, the files
+/// of a `.tar.gz` archive are parsed one after the other in archive order
+pub fn initialize_file(path: &Path, config: &Config) -> Result<Input<Box<dyn Read>>> {
+    // the archive is read by a reader thread like several files
+    if archive::is_archive(path) {
+        return Ok(Input::merge(&[path.to_path_buf()], config));
+    }
+    let file = limit(open(path, config)?, config);
+    Ok(configure(parse(file, config), config))
+}
+
+/// Rejects the lines of a reader longer than `max_record_size` when it's set
+fn limit<R: Read + Send + 'static>(reader: R, config: &Config) -> Box<dyn Read + Send> {
+    match config.max_record_size {
+        Some(limit) => Box::new(LimitReader::new(reader, limit)),
+        None => Box::new(reader),
+    }
+}
+
+/// Returns the transactions parser of a reader for the configured input format
+fn parse<R: Read>(reader: R, config: &Config) -> Input<R> {
+    match config.input_format {
+        InputFormat::Csv => Input::csv(config.csv.from_reader(reader)),
+        InputFormat::Ndjson => Input::ndjson(reader),
+    }
+}
+
+/// Applies the reading options of the configuration to a transactions parser
+fn configure<R: Read>(input: Input<R>, config: &Config) -> Input<R> {
+    input
+        .strict(config.strict)
+        .log_format(config.log_format)
+        .signed_amounts(config.signed_amounts)
+        .amounts_in_cents(config.amounts_in_cents)
+        .max_integer_digits(config.max_integer_digits)
+        .accounting_negatives(config.accounting_negatives)
+        .comment(config.comment.clone())
+}
+
+/// Opens a file read from command line.
+///
+/// When `mmap` is set the file is memory-mapped and parsed from the mapped
+/// slice, otherwise it is read through the parser's buffer
+pub fn open(path: &Path, config: &Config) -> Result<Box<dyn Read + Send>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
         Err(e) => bail!(e),
     };
+
+    let input: Box<dyn Read + Send> = if config.mmap {
+        // SAFETY: the mapping is only read, modifying the file while it's being
+        // processed is undefined behaviour
+        let mmap = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Unable to memory-map {}", path.display()))?;
+        Box::new(Cursor::new(mmap))
+    } else {
+        Box::new(file)
+    };
+    Ok(input)
+}
+
+/// Processes the input files and writes the account's balances to `writer`,
+/// every file is an independent ledger with `separate`.
+///
+/// With `two_pass` the whole input is validated first, nothing is written
+/// unless every record is valid. With `follow` the input file is followed
+/// until the process is stopped
+pub async fn process_inputs<W: Write>(writer: W, config: &Config) -> Result<RunReport> {
+    if config.two_pass {
+        validate(config)?;
+    }
+    if config.follow.is_some() {
+        let [path] = config.inputs.as_slice() else {
+            bail!("Unable to follow the input, a single file is expected");
+        };
+        return process_follow(path, writer, config, Arc::new(AtomicBool::new(false))).await;
+    }
+    if config.separate {
+        process_separate(writer, config).await
+    } else {
+        process_reader(initialize(config)?, writer, config).await
+    }
+}
+
+/// Reads every record of the input files without applying any of them, fails
+/// on the first malformed record or transaction that could never be applied,
+/// e.g. a deposit without an amount. Malformed records are never skipped
+///
+/// Returns the number of records read
+pub fn validate(config: &Config) -> Result<u64> {
+    let config = Config {
+        strict: true,
+        ..config.clone()
+    };
+    let mut input = initialize(&config)?;
+    let mut records = 0;
+    while let Some(tx) = input.read_tx()? {
+        records += 1;
+        if let Err(err) = tx.check_structure(config.policy.correction_deposits) {
+            bail!("record {records}: {err}");
+        }
+    }
+    Ok(records)
 }
 
 /// Processes transactions from file and print to stdout the account's balances as result
-pub async fn process_txs(mut reader: Reader<File>) -> Result<()> {
+///
+/// Returns a report with the number of processed and rejected transactions
+pub async fn process_txs<R: Read>(
+    input: impl Into<Input<R>>,
+    config: &Config,
+) -> Result<RunReport> {
+    process_reader(input, buffered_stdout(), config).await
+}
+
+/// Processes transactions from file and writes the account's balances to `writer`
+///
+/// If the writer is closed (e.g. the output is piped to `head`) writing stops
+/// without an error. When it's not strict a failure of the reader, e.g. an I/O
+/// error, still writes the balances of the transactions read so far before
+/// returning the error
+pub async fn process_reader<R: Read, W: Write>(
+    input: impl Into<Input<R>>,
+    writer: W,
+    config: &Config,
+) -> Result<RunReport> {
+    if config.workers > 1 {
+        return process_sharded(input, writer, config).await;
+    }
+    let engine = PaymentEngine::with_policy(config.policy);
+    let (report, _) = process_ledger(input, writer, config, engine).await?;
+    Ok(report)
+}
+
+/// Skips the first `count` records of the input without blocking on the
+/// reader threads of merged files
+async fn skip_records<R: Read>(input: &mut Input<R>, count: u64) -> Result<()> {
+    for _ in 0..count {
+        input.ready().await;
+        if input.skip_records(1)? == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Processes transactions like [`process_reader`] on `config.workers` tasks.
+/// A client is assigned to a worker by its ID so its transactions are applied
+/// in input order, the accounts of every worker are merged once they are all
+/// done and written in the configured order, whatever the workers interleaving
+async fn process_sharded<R: Read, W: Write>(
+    input: impl Into<Input<R>>,
+    writer: W,
+    config: &Config,
+) -> Result<RunReport> {
+    // every worker writes the rows of it's transactions to the same trace
+    let trace = config
+        .trace
+        .as_deref()
+        .map(ProcessTransactions::shared_trace)
+        .transpose()?;
+    let workers: Vec<_> = (0..config.workers)
+        .map(|_| ProcessTransactions::worker(config, trace.clone()))
+        .collect();
+    drop(trace);
+    // clients moved to the worker of the other client of a transfer
+    let mut moved = HashMap::new();
+    let worker = |moved: &HashMap<u16, usize>, client_id: u16| {
+        moved
+            .get(&client_id)
+            .copied()
+            .unwrap_or(usize::from(client_id) % workers.len())
+    };
+    let mut input = input.into();
+    skip_records(&mut input, config.skip).await?;
+
+    let mut read_error = None;
+    loop {
+        input.ready().await;
+        let tx = match input.read_tx() {
+            Ok(Some(tx)) => tx,
+            Ok(None) => break,
+            Err(err) if !config.strict => {
+                read_error = Some(err);
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        if config.as_of.is_some_and(|as_of| tx.tx_id > as_of) {
+            break;
+        }
+        if !config.includes(tx.client_id) {
+            continue;
+        }
+        // a transfer is applied atomically by a single worker, the destination
+        // moves to the source's one once it's earlier transactions are applied
+        let source = worker(&moved, tx.client_id);
+        if let Some(destination_id) = tx
+            .destination_id
+            .filter(|_| tx.tx_type == TransactionEnum::Transfer)
+        {
+            let destination = worker(&moved, destination_id);
+            if destination != source {
+                let client = match workers[destination].take(destination_id).await {
+                    Ok(client) => client,
+                    Err(_) => break,
+                };
+                if workers[source]
+                    .tx_tx
+                    .send(Command::Insert(Box::new(client)))
+                    .is_err()
+                {
+                    break;
+                }
+                moved.insert(destination_id, source);
+            }
+        }
+        if workers[source].tx_tx.send(Command::Tx(tx)).is_err() {
+            break;
+        }
+    }
+
+    let mut report = RunReport {
+        skipped: input.skipped(),
+        ..Default::default()
+    };
+    let mut accounts = vec![];
+    for mut worker in workers {
+        drop(worker.tx_tx);
+        let worker_report = worker.handle.await??;
+        report.processed += worker_report.processed;
+        report.rejected += worker_report.rejected;
+        // every account was sent before the worker was done
+        while let Ok(account) = worker.rx_result.try_recv() {
+            accounts.push(account);
+        }
+    }
+    config.sort_by.sort(&mut accounts);
+    report.accounts = accounts.len();
+
+    output::write_snapshot(accounts, writer, config).await;
+    if let Some(err) = read_error {
+        return Err(err.context(format!(
+            "The input failed after {} records, the balances include them",
+            input.records()
+        )));
+    }
+    Ok(report)
+}
+
+/// Processes transactions like [`process_reader`] applying them to `engine`,
+/// returns the engine with the report so it can be reset for the next ledger
+async fn process_ledger<R: Read, W: Write>(
+    input: impl Into<Input<R>>,
+    writer: W,
+    config: &Config,
+    engine: PaymentEngine,
+) -> Result<(RunReport, PaymentEngine)> {
+    // a run killed after saving a checkpoint resumes from it's accounts
+    let checkpoint = match &config.checkpoint {
+        Some(path) => Checkpoint::load(path)?,
+        None => None,
+    };
+    let applied = checkpoint.as_ref().map_or(config.skip, |checkpoint| {
+        config.skip.max(checkpoint.records)
+    });
+
     // crate a new instance of a ProcessTransaction task, it will handle all the logic by calculating the balances based on transaction type
     // it also will display the as tdout the result of its calculations
-    let mut process_tx = ProcessTransactions::new();
-    let mut record = ByteRecord::new();
+    let mut process_tx = match checkpoint {
+        Some(checkpoint) => ProcessTransactions::resume(config, checkpoint)?,
+        None => ProcessTransactions::with_engine(config, engine)?,
+    };
+    let mut input = input.into();
+    // the first records were already applied by a previous run, they aren't
+    // parsed so a malformed one doesn't fail the run
+    skip_records(&mut input, applied).await?;
 
-    while reader.read_byte_record(&mut record)? {
-        // for every record we must ensure it has the right amount of inputs on the line
-        let tx: Transaction = record.deserialize(match record.len() {
-            3 => Some(&THREE_INPUTS),
-            4 => Some(&FOUR_INPUTS),
-            _ => {
-                bail!("Error reading data, invalid length of {}.", record.len())
+    let mut saved = applied;
+    let mut read_error = None;
+    loop {
+        input.ready().await;
+        let tx = match input.read_tx() {
+            Ok(Some(tx)) => tx,
+            Ok(None) => break,
+            Err(err) if !config.strict => {
+                read_error = Some(err);
+                break;
             }
-        })?;
-        // send every record to ProcessTransaction task in the same order as it is read from the file
-        let _ = process_tx.tx_tx.send(tx);
+            Err(err) => return Err(err),
+        };
+        // the state as of the given transaction is complete
+        if config.as_of.is_some_and(|as_of| tx.tx_id > as_of) {
+            break;
+        }
+        // the other clients are left out of the run
+        if !config.includes(tx.client_id) {
+            continue;
+        }
+        // send every record to ProcessTransaction task in the same order as it is read from the file,
+        // it stops early once too many transactions were rejected
+        if process_tx.tx_tx.send(Command::Tx(tx)).is_err() {
+            break;
+        }
+        if let Some(path) = &config.checkpoint {
+            // the malformed records skipped count as well, a checkpoint is due
+            // once a multiple of the interval was passed
+            let records = input.records();
+            if records / config.checkpoint_every > saved / config.checkpoint_every {
+                saved = records;
+                if let Err(err) = process_tx.checkpoint(path.clone(), records).await {
+                    // the reason the task stopped comes first
+                    process_tx.handle.await??;
+                    return Err(err);
+                }
+            }
+        }
     }
     // after the file has been read completly drop the channel, in this way
     // the ProcessTransaction task will proceed evaluating transactions
     drop(process_tx.tx_tx);
 
-    // create a CSV writer
-    let mut writer = Writer::from_writer(std::io::stdout());
-    // Write the header values to the record to printout in the output
-    _ = writer.write_record(&["client", "available", "held", "total", "locked"]);
+    // the accounts are only written once the run succeeded, they are buffered
+    // by the channel meanwhile
+    let report = RunReport {
+        skipped: input.skipped(),
+        ..process_tx.handle.await??
+    };
+    let engine = process_tx
+        .engine
+        .await
+        .context("The processing task stopped unexpectedly!")?;
+    output::write_accounts(&mut process_tx.rx_result, writer, config).await;
+    // the checkpoint is kept to resume the incomplete run
+    if let Some(err) = read_error {
+        return Err(err.context(format!(
+            "The input failed after {} records, the balances include them",
+            input.records()
+        )));
+    }
+    // the run is complete, there is nothing left to resume
+    if let Some(path) = &config.checkpoint {
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("Unable to remove checkpoint {}", path.display()))?;
+        }
+    }
+    Ok((report, engine))
+}
+
+/// Processes a file that is still being appended to, like `tail -f`. The
+/// appended lines are applied as they are written and the account's balances
+/// are written to `writer` at every `follow` interval, as long as they change.
+///
+/// Following ends once `stop` is set, then the balances are written a last time
+pub async fn process_follow<W: Write>(
+    path: &Path,
+    mut writer: W,
+    config: &Config,
+    stop: Arc<AtomicBool>,
+) -> Result<RunReport> {
+    let Some(interval) = config.follow else {
+        bail!("Unable to follow the input, no interval was configured");
+    };
+    let mut process_tx = ProcessTransactions::new(config)?;
+    let mut file = FollowedFile::spawn(path, config, stop.clone())?;
 
-    // write every record received from ProcessTransaction task to stdout
-    while let Some(record) = process_tx.rx_result.recv().await {
-        if let Err(err) = writer.write_byte_record(&record) {
-            {
-                log::error!("Error in writing records! \n {err}")
+    let mut changed = false;
+    let mut written = Instant::now();
+    loop {
+        let wait = interval.saturating_sub(written.elapsed());
+        match tokio::time::timeout(wait, file.rx.recv()).await {
+            Ok(Some(tx)) => {
+                if config.as_of.is_some_and(|as_of| tx.tx_id > as_of) {
+                    break;
+                }
+                let applied = config.includes(tx.client_id);
+                // it stops early once too many transactions were rejected
+                if applied && process_tx.tx_tx.send(Command::Tx(tx)).is_err() {
+                    break;
+                }
+                changed |= applied;
             }
+            Err(_) => {}
+            // the file was read until following stopped
+            Ok(None) => break,
+        }
+        if written.elapsed() >= interval {
+            if changed {
+                match process_tx.snapshot().await {
+                    Ok(accounts) => output::write_snapshot(accounts, &mut writer, config).await,
+                    Err(_) => break,
+                }
+                changed = false;
+            }
+            written = Instant::now();
         }
     }
-    Ok(())
+    // the reader may still be waiting for more lines
+    stop.store(true, Ordering::Release);
+    drop(process_tx.tx_tx);
+
+    // the reason the task stopped comes first
+    let report = process_tx.handle.await??;
+    let report = RunReport {
+        skipped: file.finish().await?,
+        ..report
+    };
+    output::write_accounts(&mut process_tx.rx_result, writer, config).await;
+    Ok(report)
+}
+
+/// Processes the transactions of several readers, e.g. files, stdin or
+/// sockets, as a single stream and writes the account's balances to `writer`.
+///
+/// The readers are read one after the other in the configured input format,
+/// every CSV reader starts with it's own header
+pub async fn process_readers<W: Write>(
+    readers: Vec<Box<dyn Read>>,
+    writer: W,
+    config: &Config,
+) -> Result<RunReport> {
+    let Some(input) = readers
+        .into_iter()
+        .map(|reader| parse(reader, config))
+        .reduce(Input::chain)
+    else {
+        bail!("Unable to process transactions, no reader was given");
+    };
+    process_reader(configure(input, config), writer, config).await
+}
+
+/// Applies transactions already parsed in memory with the default policy and
+/// returns the accounts ordered by client ID, no input is parsed
+///
+/// Rejected transactions are logged and skipped like in a file
+pub fn process_transactions(txs: Vec<Transaction>) -> Vec<Account> {
+    let mut engine = PaymentEngine::new();
+    for tx in txs {
+        if let Err(err) = engine.process(tx) {
+            log::error!("Error processing transaction!\n{err}");
+        }
+    }
+    engine.accounts()
+}
+
+/// Processes every file as an independent ledger and writes to `writer` a
+/// section per file, each section starts with a `# <path>` line followed by
+/// the account's balances of that file
+///
+/// Returns a report summing the runs of every file
+pub async fn process_separate<W: Write>(mut writer: W, config: &Config) -> Result<RunReport> {
+    let mut report = RunReport::default();
+    // the engine is reset between the files instead of reallocated
+    let mut engine = PaymentEngine::with_policy(config.policy);
+    for path in &config.inputs {
+        if let Err(err) = writeln!(writer, "# {}", path.display()) {
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                break;
+            }
+            bail!(err);
+        }
+        let (file_report, used) =
+            process_ledger(initialize_file(path, config)?, &mut writer, config, engine).await?;
+        engine = used;
+        engine.reset();
+        report.processed += file_report.processed;
+        report.rejected += file_report.rejected;
+        report.skipped += file_report.skipped;
+        report.accounts += file_report.accounts;
+    }
+    Ok(report)
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::{
-        process::ProcessTransactions,
-        Transaction, {FOUR_INPUTS, THREE_INPUTS},
+        Config, InputConfig, InputFormat, OutputFormat, RunOutcome, RunReport, SortBy,
+        TransactionEnum,
     };
     use anyhow::Result;
     use csv::{ByteRecord, Reader, ReaderBuilder, Trim};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
     use serde::Deserialize;
-    use std::fs::File;
+    use std::{
+        collections::HashSet,
+        fs::{self, File},
+        io::{self, Read, Write},
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
-    fn initialize() -> Result<Reader<File>> {
-        match File::open("csv_files/balance_test.csv") {
-            Ok(file) => {
-                return Ok(ReaderBuilder::new()
-                    .delimiter(b',')
-                    .flexible(true)
-                    .trim(Trim::All)
-                    .from_reader(file))
-            }
-            Err(e) => panic!("{e}"),
-        };
+    /// Path of a temporary file unique to the test run, the file is removed
+    /// once the test is over even when it fails
+    pub(crate) struct TempPath(PathBuf);
+
+    impl TempPath {
+        pub(crate) fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("payment_engine_{}_{name}", std::process::id())))
+        }
     }
 
-    async fn process_txs(mut reader: Reader<File>) -> Result<Vec<Output>> {
-        // crate a new instance of a ProcessTransaction task, it will handle all the logic by calculating the balances based on transaction type
-        // it also will display the as tdout the result of its calculations
-        let mut process_tx = ProcessTransactions::new();
-        let mut record = ByteRecord::new();
+    impl std::ops::Deref for TempPath {
+        type Target = PathBuf;
 
-        while reader.read_byte_record(&mut record)? {
-            // for every record we must ensure it has the right amount of inputs on the line
-            let tx: Transaction = record.deserialize(match record.len() {
-                3 => Some(&THREE_INPUTS),
-                4 => Some(&FOUR_INPUTS),
-                _ => {
-                    panic!("Error reading data, invalid length of {}.", record.len())
-                }
-            })?;
-            // send every record to ProcessTransaction task in the same order as it is read from the file
-            let _ = process_tx.tx_tx.send(tx);
+        fn deref(&self) -> &PathBuf {
+            &self.0
+        }
+    }
+
+    impl AsRef<std::path::Path> for TempPath {
+        fn as_ref(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            _ = fs::remove_file(&self.0);
         }
-        // after the file has been read completly drop the channel, in this way
-        // the ProcessTransaction task will proceed evaluating transactions
-        drop(process_tx.tx_tx);
+    }
+
+    thread_local! {
+        /// Records logged on the thread while they are captured
+        static LOGGED: std::cell::RefCell<Vec<(log::Level, String)>> =
+            const { std::cell::RefCell::new(vec![]) };
+    }
+
+    /// Logger keeping the records of every thread apart, a test only sees the
+    /// ones of it's own thread
+    struct Captured;
 
-        let mut result = vec![];
-        // write every record received from ProcessTransaction task to stdout
-        while let Some(record) = process_tx.rx_result.recv().await {
-            let output: Output = record.deserialize(None).unwrap();
-            result.push(output)
+    impl log::Log for Captured {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGGED.with(|logged| {
+                logged
+                    .borrow_mut()
+                    .push((record.level(), record.args().to_string()))
+            });
         }
-        Ok(result)
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: Captured = Captured;
+
+    /// Captures the records logged on the current thread from now on, a
+    /// current thread runtime logs the ones of it's tasks there as well
+    pub(crate) fn capture_logs() {
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+        LOGGED.with(|logged| logged.borrow_mut().clear());
+    }
+
+    /// Returns the records logged on the current thread since they were
+    /// captured
+    pub(crate) fn logged() -> Vec<(log::Level, String)> {
+        LOGGED.with(|logged| logged.take())
+    }
+
+    fn initialize(path: &str) -> Result<Reader<File>> {
+        match File::open(path) {
+            Ok(file) => Ok(InputConfig::default().from_reader(file)),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    async fn process_txs<R: Read>(
+        input: impl Into<super::Input<R>>,
+        config: &Config,
+    ) -> Result<(Vec<Output>, RunReport)> {
+        let mut output = vec![];
+        let report = super::process_reader(input, &mut output, config).await?;
+        let result = ReaderBuilder::new()
+            .from_reader(output.as_slice())
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+        Ok((result, report))
     }
 
     #[derive(Deserialize, Debug, PartialEq)]
@@ -145,7 +690,7 @@ mod tests {
 
     #[tokio::test]
     async fn calculate_balance() {
-        let compare_tx = vec![
+        let compare_tx = [
             Output {
                 client: "1".to_string(),
                 available: "17.0000".to_string(),
@@ -162,17 +707,1425 @@ mod tests {
             },
         ];
 
-        process_txs(initialize().unwrap())
+        // the accounts are ordered by client id
+        let (output, _) = process_txs(
+            initialize("csv_files/balance_test.csv").unwrap(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output, compare_tx);
+    }
+
+    #[tokio::test]
+    async fn run_report() {
+        let (_, report) = process_txs(
+            initialize("csv_files/tx_test.csv").unwrap(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+
+        // the dispute, resolve and chargeback reference a transaction that doesn't exist
+        assert_eq!(
+            report,
+            RunReport {
+                processed: 2,
+                rejected: 3,
+                accounts: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn mmap_input() {
+        let mut config = Config {
+            inputs: vec![PathBuf::from("csv_files/balance_test.csv")],
+            ..Default::default()
+        };
+        let buffered = process_txs(super::initialize(&config).unwrap(), &config)
             .await
-            .unwrap()
-            .iter()
-            // process_tx output is not ordered to in order to compare it to the
-            // compare_tx vec we must search by matching client ids
-            .for_each(|item| {
-                assert_eq!(
-                    item,
-                    compare_tx.iter().find(|e| item.client == e.client).unwrap()
-                )
-            });
+            .unwrap();
+
+        // both readers must produce the exact same accounts
+        config.mmap = true;
+        let mapped = process_txs(super::initialize(&config).unwrap(), &config)
+            .await
+            .unwrap();
+        assert_eq!(buffered, mapped);
+    }
+
+    #[test]
+    fn trim_mode() {
+        let mut config = Config {
+            inputs: vec![PathBuf::from("csv_files/tx_test.csv")],
+            csv: InputConfig::builder().trim(Trim::None).build(),
+            ..Default::default()
+        };
+        let mut record = ByteRecord::new();
+
+        // without trimming the spaces following the delimiters are kept
+        let mut reader = config
+            .csv
+            .from_reader(super::open(&config.inputs[0], &config).unwrap());
+        assert_eq!(
+            reader.byte_headers().unwrap(),
+            vec!["type", " client", " tx", " amount"]
+        );
+        reader.read_byte_record(&mut record).unwrap();
+        assert_eq!(record, vec!["deposit", " 1", " 1", " 10.0"]);
+
+        config.csv = InputConfig::builder().trim(Trim::Headers).build();
+        let mut reader = config
+            .csv
+            .from_reader(super::open(&config.inputs[0], &config).unwrap());
+        assert_eq!(
+            reader.byte_headers().unwrap(),
+            vec!["type", "client", "tx", "amount"]
+        );
+        reader.read_byte_record(&mut record).unwrap();
+        assert_eq!(record, vec!["deposit", " 1", " 1", " 10.0"]);
+    }
+
+    #[tokio::test]
+    async fn sort_by_total() {
+        let clients = |config: Config| async move {
+            process_txs(initialize("csv_files/tx.csv").unwrap(), &config)
+                .await
+                .unwrap()
+                .0
+                .into_iter()
+                .map(|output| output.client)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            clients(Config::default()).await,
+            vec!["17438", "17526", "23187", "35978", "39469", "58598", "62104"]
+        );
+        // accounts with the same total are ordered by client id
+        assert_eq!(
+            clients(Config {
+                sort_by: SortBy::Total,
+                ..Default::default()
+            })
+            .await,
+            vec!["58598", "35978", "17526", "17438", "23187", "39469", "62104"]
+        );
+    }
+
+    #[tokio::test]
+    async fn summary_only() {
+        let config = Config {
+            output_format: OutputFormat::Summary,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        super::process_reader(
+            initialize("csv_files/balance_test.csv").unwrap(),
+            &mut output,
+            &config,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "clients=2 locked=1 total=126.0000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn locked_only() {
+        let config = Config {
+            locked_only: true,
+            ..Default::default()
+        };
+        let (output, report) =
+            process_txs(initialize("csv_files/balance_test.csv").unwrap(), &config)
+                .await
+                .unwrap();
+
+        // client 2 has a disputed deposit but only client 1 was charged back
+        assert_eq!(
+            output
+                .iter()
+                .map(|output| (output.client.as_str(), output.locked))
+                .collect::<Vec<_>>(),
+            vec![("1", true)]
+        );
+        assert_eq!(report.accounts, 1);
+    }
+
+    #[tokio::test]
+    async fn hide_inactive() {
+        // client 2 only has a dispute of a missing transaction and a declined withdrawal
+        let data = "type,client,tx,amount\ndeposit,1,1,10.0\ndispute,2,1\nwithdrawal,2,2,5.0\n";
+        let clients = |config: Config| async move {
+            process_txs(InputConfig::default().from_reader(data.as_bytes()), &config)
+                .await
+                .unwrap()
+                .0
+                .into_iter()
+                .map(|output| output.client)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(clients(Config::default()).await, vec!["1", "2"]);
+        let config = Config {
+            hide_inactive: true,
+            ..Default::default()
+        };
+        assert_eq!(clients(config).await, vec!["1"]);
+    }
+
+    #[test]
+    fn merge_by_timestamp() {
+        let config = Config {
+            inputs: vec![
+                PathBuf::from("csv_files/merge_a.csv"),
+                PathBuf::from("csv_files/merge_b.csv"),
+            ],
+            ..Default::default()
+        };
+        let mut input = super::initialize(&config).unwrap();
+        let mut txs = vec![];
+        while let Some(tx) = input.read_tx().unwrap() {
+            txs.push((tx.tx_type, tx.tx_id, tx.timestamp));
+        }
+        assert_eq!(
+            txs,
+            vec![
+                (TransactionEnum::Deposit, 1, Some(1)),
+                (TransactionEnum::Deposit, 2, Some(2)),
+                (TransactionEnum::Withdrawal, 3, Some(3)),
+                (TransactionEnum::Dispute, 1, Some(4)),
+                (TransactionEnum::Resolve, 1, Some(5)),
+            ]
+        );
+
+        // without timestamps the files are read one after the other
+        let config = Config {
+            inputs: vec![
+                PathBuf::from("csv_files/tx_test.csv"),
+                PathBuf::from("csv_files/balance_test.csv"),
+            ],
+            ..Default::default()
+        };
+        let mut input = super::initialize(&config).unwrap();
+        let mut tx_ids = vec![];
+        while let Some(tx) = input.read_tx().unwrap() {
+            tx_ids.push(tx.tx_id);
+        }
+        assert_eq!(tx_ids, vec![1, 4, 3, 3, 3, 1, 1, 2, 2, 3, 3, 3, 3, 4, 3]);
+    }
+
+    /// Returns a dispute heavy workload of 8 clients as the client of every
+    /// record and the record
+    fn dispute_heavy() -> Vec<(u32, String)> {
+        let mut lines = vec![];
+        for tx in 1..=200u32 {
+            let client = tx % 8 + 1;
+            lines.push((client, format!("deposit,{client},{tx},{}.5", tx % 7)));
+            if tx % 2 == 0 {
+                lines.push((client, format!("dispute,{client},{tx},")));
+            }
+            match tx % 6 {
+                0 => lines.push((client, format!("resolve,{client},{tx},"))),
+                2 => lines.push((client, format!("chargeback,{client},{tx},"))),
+                _ => {}
+            }
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn merge_deterministic() {
+        // several transactions share a timestamp
+        let lines = dispute_heavy();
+        let write = |name: &str, shard: Option<u32>| {
+            let path = TempPath::new(name);
+            let mut file = File::create(&path).unwrap();
+            writeln!(file, "type,client,tx,amount,timestamp").unwrap();
+            for (i, (client, line)) in lines.iter().enumerate() {
+                if shard.is_none_or(|shard| client % 4 == shard) {
+                    writeln!(file, "{line},{}", i / 4).unwrap();
+                }
+            }
+            path
+        };
+        let run = |inputs| async move {
+            let config = Config {
+                inputs,
+                ..Default::default()
+            };
+            let mut output = vec![];
+            super::process_inputs(&mut output, &config).await.unwrap();
+            output
+        };
+
+        let single = write("merge_single.csv", None);
+        let single = run(vec![single.to_path_buf()]).await;
+        // every client is read by a single reader thread, so its transactions
+        // keep their order whatever the threads interleaving is
+        let shards: Vec<_> = (0..4)
+            .map(|shard| write(&format!("merge_shard_{shard}.csv"), Some(shard)))
+            .collect();
+        let paths: Vec<_> = shards.iter().map(|path| path.to_path_buf()).collect();
+        for _ in 0..10 {
+            assert_eq!(run(paths.clone()).await, single);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sharded_deterministic() {
+        let data: String = std::iter::once("type,client,tx,amount".to_string())
+            .chain(dispute_heavy().into_iter().map(|(_, line)| line))
+            .map(|line| line + "\n")
+            .collect();
+        let run = |workers| {
+            let data = &data;
+            async move {
+                let config = Config {
+                    workers,
+                    ..Default::default()
+                };
+                let mut output = vec![];
+                let input = InputConfig::default().from_reader(data.as_bytes());
+                let report = super::process_reader(input, &mut output, &config)
+                    .await
+                    .unwrap();
+                (output, report)
+            }
+        };
+
+        let single = run(1).await;
+        assert_eq!(single.1.accounts, 8);
+        // the clients of every worker are applied concurrently
+        for _ in 0..10 {
+            assert_eq!(run(4).await, single);
+        }
+
+        // the clients of a transfer between workers are applied by one of them,
+        // with their history and ended state
+        let data = "type,client,tx,amount,destination
+deposit,1,1,10.0,
+deposit,2,2,10.0,
+deposit,3,3,1.0,
+end_client,3,4,,
+transfer,1,5,5.0,2
+deposit,2,6,1.0,
+dispute,2,2,,
+transfer,2,7,3.0,6
+transfer,1,8,1.0,3
+transfer,7,9,1.0,1
+resolve,2,2,,
+transfer,6,10,1.0,1
+";
+        let run = |workers| async move {
+            let config = Config {
+                workers,
+                ..Default::default()
+            };
+            let mut output = vec![];
+            let input = InputConfig::default().from_reader(data.as_bytes());
+            let report = super::process_reader(input, &mut output, &config)
+                .await
+                .unwrap();
+            (String::from_utf8(output).unwrap(), report)
+        };
+        let single = run(1).await;
+        assert_eq!((single.1.processed, single.1.rejected), (10, 2));
+        assert_eq!(
+            single.0,
+            "client,available,held,total,locked
+1,6.0000,0.0000,6.0000,false
+2,13.0000,0.0000,13.0000,false
+3,1.0000,0.0000,1.0000,false
+6,2.0000,0.0000,2.0000,false
+7,0.0000,0.0000,0.0000,false
+"
+        );
+        for _ in 0..10 {
+            assert_eq!(run(4).await, single);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sharded_trace() {
+        let data: String = std::iter::once("type,client,tx,amount".to_string())
+            .chain(dispute_heavy().into_iter().map(|(_, line)| line))
+            .map(|line| line + "\n")
+            .collect();
+        let run = |workers| {
+            let data = &data;
+            async move {
+                let path = TempPath::new(&format!("sharded_trace_{workers}.csv"));
+                let config = Config {
+                    workers,
+                    trace: Some(path.to_path_buf()),
+                    ..Default::default()
+                };
+                let input = InputConfig::default().from_reader(data.as_bytes());
+                let report = super::process_reader(input, io::sink(), &config)
+                    .await
+                    .unwrap();
+                let trace = fs::read_to_string(&path).unwrap();
+                let (header, records) = trace.split_once('\n').unwrap();
+                // the records of a client keep their order
+                let mut records: Vec<_> = records.lines().map(str::to_string).collect();
+                records.sort_by_key(|record| record.split(',').nth(1).unwrap().to_string());
+                // a record per applied transaction
+                assert_eq!(records.len() as u64, report.processed);
+                (header.to_string(), records)
+            }
+        };
+
+        let single = run(1).await;
+        // the workers write to the trace concurrently, their records are
+        // never interleaved
+        assert_eq!(run(4).await, single);
+    }
+
+    /// Output forwarding every write to the test, e.g. while following
+    struct Forward(tokio::sync::mpsc::UnboundedSender<Vec<u8>>);
+
+    impl Write for Forward {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.0.send(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reads the forwarded output until it holds `expected`
+    async fn wait_for(
+        output: &mut String,
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+        expected: &str,
+    ) {
+        while !output.contains(expected) {
+            output.push_str(&String::from_utf8(rx.recv().await.unwrap()).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn follow() {
+        let path = TempPath::new("follow.csv");
+        fs::write(&path, "type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut output = String::new();
+        let config = Config {
+            follow: Some(Duration::from_millis(10)),
+            max_record_size: Some(64),
+            ..Default::default()
+        };
+        let following = {
+            let (path, config, stop) = (path.to_path_buf(), config.clone(), stop.clone());
+            tokio::spawn(
+                async move { super::process_follow(&path, Forward(tx), &config, stop).await },
+            )
+        };
+        wait_for(&mut output, &mut rx, "1,10.0000,0.0000,10.0000,false\n").await;
+
+        // a line is only parsed once it's complete, whenever it's read
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "withdrawal,1,2,").unwrap();
+        file.flush().unwrap();
+        writeln!(file, "4.0\ndeposit,2,3,1.5").unwrap();
+        wait_for(&mut output, &mut rx, "2,1.5000,0.0000,1.5000,false\n").await;
+        stop.store(true, Ordering::Release);
+        let report = following.await.unwrap().unwrap();
+        // the last balances were written before following returned
+        while let Some(chunk) = rx.recv().await {
+            output.push_str(&String::from_utf8(chunk).unwrap());
+        }
+
+        // the balances are written again once the appended rows are applied
+        let snapshots: Vec<_> = output
+            .split("client,available,held,total,locked\n")
+            .skip(1)
+            .collect();
+        assert_eq!(snapshots[0], "1,10.0000,0.0000,10.0000,false\n");
+        assert_eq!(
+            snapshots.last().unwrap(),
+            &"1,6.0000,0.0000,6.0000,false\n2,1.5000,0.0000,1.5000,false\n"
+        );
+        assert_eq!((report.processed, report.rejected), (3, 0));
+
+        // the lines are limited like in any other input
+        writeln!(file, "deposit,3,4,{}", "1".repeat(64)).unwrap();
+        let stop = Arc::new(AtomicBool::new(true));
+        let err = super::process_follow(&path, io::sink(), &config, stop)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("record exceeds the maximum size of 64 bytes"));
+    }
+
+    #[tokio::test]
+    async fn tar_gz_archive() {
+        let path = TempPath::new("archive.tar.gz");
+        let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&path).unwrap(),
+            flate2::Compression::default(),
+        ));
+        // the entries are read in archive order, not by name
+        for (name, data) in [
+            ("b.csv", "type,client,tx,amount\ndeposit,1,1,10.0\n"),
+            (
+                "a.csv",
+                "type,client,tx,amount\nwithdrawal,1,2,4.0\ndispute,1,2\n",
+            ),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(&mut header, name, data.as_bytes())
+                .unwrap();
+        }
+        archive.into_inner().unwrap().finish().unwrap();
+
+        let config = Config {
+            inputs: vec![path.to_path_buf()],
+            ..Default::default()
+        };
+        let (output, report) = process_txs(super::initialize(&config).unwrap(), &config)
+            .await
+            .unwrap();
+        assert_eq!(
+            (output[0].available.as_str(), output[0].held.as_str()),
+            ("2.0000", "4.0000")
+        );
+        assert_eq!((report.processed, report.rejected), (3, 0));
+    }
+
+    #[tokio::test]
+    async fn separate_ledgers() {
+        let config = Config {
+            inputs: vec![
+                PathBuf::from("csv_files/balance_test.csv"),
+                PathBuf::from("csv_files/tx_test.csv"),
+            ],
+            separate: true,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        let report = super::process_separate(&mut output, &config).await.unwrap();
+
+        // client 1 of the second file doesn't see the balance it has in the first one
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "# csv_files/balance_test.csv
+client,available,held,total,locked
+1,17.0000,0.0000,17.0000,true
+2,9.0000,100.0000,109.0000,false
+# csv_files/tx_test.csv
+client,available,held,total,locked
+1,7.0000,0.0000,7.0000,false
+"
+        );
+        assert_eq!((report.processed, report.accounts), (12, 3));
+
+        // the IDs used by the first file don't reject the ones of the second
+        let config = Config {
+            inputs: vec![PathBuf::from("csv_files/tx_test.csv"); 2],
+            policy: super::Policy {
+                unique_tx_ids: true,
+                ..Default::default()
+            },
+            ..config
+        };
+        let report = super::process_separate(io::sink(), &config).await.unwrap();
+        assert_eq!((report.processed, report.rejected), (4, 6));
+    }
+
+    #[tokio::test]
+    async fn skip_malformed() {
+        let data = "type,client,tx,amount\ndeposit,1,1,abc\ndeposit,1,2,5.0\n";
+        let input = || InputConfig::default().from_reader(data.as_bytes());
+
+        let err = process_txs(input(), &Config::default()).await.unwrap_err();
+        assert_eq!(err.to_string(), "line 2: could not parse amount 'abc'");
+
+        let (output, report) =
+            process_txs(super::Input::csv(input()).strict(false), &Config::default())
+                .await
+                .unwrap();
+        assert_eq!(output[0].available, "5.0000");
+        assert_eq!((report.processed, report.skipped), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn header_toggle() {
+        let run = |name: &str, data: &str, args: &[&str]| {
+            let path = TempPath::new(name);
+            fs::write(&path, data).unwrap();
+            let args = args
+                .iter()
+                .map(|arg| arg.to_string())
+                .chain([path.display().to_string()]);
+            let config = Config::from_args(args).unwrap();
+            async move {
+                let mut output = vec![];
+                let report = super::process_inputs(&mut output, &config).await.unwrap();
+                // the file is removed once it was processed
+                drop(path);
+                (String::from_utf8(output).unwrap(), report.processed)
+            }
+        };
+
+        let expected = "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n";
+        // the first data row is applied whether the file has a header or not
+        assert_eq!(
+            run(
+                "header.csv",
+                "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n",
+                &[]
+            )
+            .await,
+            (expected.to_string(), 2)
+        );
+        assert_eq!(
+            run(
+                "no_header.csv",
+                "deposit,1,1,10.0\nwithdrawal,1,2,4.0\ndispute,1,2\nresolve,1,2\n",
+                &["--no-header"]
+            )
+            .await,
+            (expected.to_string(), 4)
+        );
+    }
+
+    #[tokio::test]
+    async fn dotted_amounts() {
+        // a trailing or leading dot is parsed as it is, no fallback is needed
+        let data =
+            "type,client,tx,amount\ndeposit,1,1,10.\ndeposit,2,2,.5\ndeposit,3,3,\"1,000.\"\n";
+        let (output, report) = process_txs(
+            InputConfig::default().from_reader(data.as_bytes()),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            output
+                .iter()
+                .map(|output| output.total.as_str())
+                .collect::<Vec<_>>(),
+            vec!["10.0000", "0.5000", "1000.0000"]
+        );
+        assert_eq!(report.rejected, 0);
+    }
+
+    #[tokio::test]
+    async fn run_outcome() {
+        let run = |data: &'static str, strict, max_errors| async move {
+            let config = Config {
+                max_errors,
+                ..Default::default()
+            };
+            let input = super::Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+                .strict(strict);
+            RunOutcome::of(&super::process_reader(input, io::sink(), &config).await)
+        };
+
+        let data = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n";
+        assert_eq!(run(data, true, None).await, RunOutcome::Clean);
+        // a rejected withdrawal and a malformed record
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,4.0\ndeposit,x,3,1.0\n";
+        assert_eq!(
+            run(data, false, None).await,
+            RunOutcome::Partial {
+                rejected: 1,
+                skipped: 1
+            }
+        );
+        assert_eq!(run(data, false, Some(1)).await, RunOutcome::Aborted);
+        // a malformed record fails a strict run
+        assert_eq!(run(data, true, None).await, RunOutcome::Aborted);
+    }
+
+    #[tokio::test]
+    async fn max_errors() {
+        // every withdrawal exceeds the balance
+        let data = "type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,1,2,5.0
+withdrawal,1,3,5.0
+withdrawal,1,4,5.0
+deposit,1,5,1.0
+";
+        let run = |max_errors| async move {
+            let config = Config {
+                max_errors,
+                ..Default::default()
+            };
+            let mut output = vec![];
+            let report = super::process_reader(
+                InputConfig::default().from_reader(data.as_bytes()),
+                &mut output,
+                &config,
+            )
+            .await;
+            (report, output)
+        };
+
+        let (report, _) = run(Some(4)).await;
+        assert_eq!(report.unwrap().rejected, 3);
+
+        // the run is aborted without writing any account
+        let (report, output) = run(Some(3)).await;
+        assert_eq!(
+            report.unwrap_err().to_string(),
+            "Aborting the run, 3 transactions were rejected!"
+        );
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn skip_records() {
+        let data = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\ndeposit,1,3,1.0\n";
+        let config = Config {
+            skip: 2,
+            ..Default::default()
+        };
+        let (output, report) =
+            process_txs(InputConfig::default().from_reader(data.as_bytes()), &config)
+                .await
+                .unwrap();
+
+        assert_eq!(output[0].total, "1.0000");
+        assert_eq!((report.processed, report.rejected), (1, 0));
+
+        // the skipped records aren't parsed, a malformed one doesn't fail a strict run
+        let data = "type,client,tx,amount\ndeposit,1,1,abc\ndeposit,1,2,5.0\ndeposit,1,3,1.0\n";
+        let (output, report) =
+            process_txs(InputConfig::default().from_reader(data.as_bytes()), &config)
+                .await
+                .unwrap();
+        assert_eq!(output[0].total, "1.0000");
+        assert_eq!((report.processed, report.skipped), (1, 0));
+    }
+
+    /// Writer simulating an output pipe closed by the reader
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn closed_output() {
+        // enough accounts to overflow the buffer of the CSV writer
+        let mut data = String::from("type,client,tx,amount\n");
+        for client in 1..=1000 {
+            data.push_str(&format!("deposit,{client},{client},1.0\n"));
+        }
+        let report = super::process_reader(
+            InputConfig::default().from_reader(data.as_bytes()),
+            ClosedPipe,
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.accounts, 1000);
+    }
+
+    #[tokio::test]
+    async fn ndjson_input() {
+        let config = Config {
+            inputs: vec![PathBuf::from("csv_files/balance_test.ndjson")],
+            input_format: InputFormat::Ndjson,
+            ..Default::default()
+        };
+        let (ndjson, ndjson_report) = process_txs(super::initialize(&config).unwrap(), &config)
+            .await
+            .unwrap();
+
+        let (csv, csv_report) = process_txs(
+            initialize("csv_files/balance_test.csv").unwrap(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ndjson, csv);
+        assert_eq!(ndjson_report, csv_report);
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys() {
+        let once = process_txs(
+            initialize("csv_files/idempotency.csv").unwrap(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+
+        // the replayed file shares the keys of the first delivery
+        let replayed = super::Input::from(initialize("csv_files/idempotency.csv").unwrap())
+            .chain(initialize("csv_files/idempotency.csv").unwrap().into());
+        let (output, report) = process_txs(replayed, &Config::default()).await.unwrap();
+        assert_eq!(output, once.0);
+        assert_eq!(
+            (output[0].available.as_str(), output[1].held.as_str()),
+            ("7.5000", "5.0000")
+        );
+        assert_eq!((report.processed, report.rejected), (4, 4));
+    }
+
+    /// Reader failing once it's data is over, like a run killed mid-file
+    struct Killed<'a>(&'a [u8]);
+
+    impl Read for Killed<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::Error::other("killed"));
+            }
+            self.0.read(buf)
+        }
+    }
+
+    #[tokio::test]
+    async fn checkpoint_resume() {
+        let path = TempPath::new("checkpoint_resume.ckpt");
+        let config = Config {
+            checkpoint: Some(path.to_path_buf()),
+            checkpoint_every: 2,
+            ..Default::default()
+        };
+        let data = std::fs::read("csv_files/balance_test.csv").unwrap();
+
+        // the run is killed in the middle of a record
+        let killed = Killed(&data[..data.len() / 2 + 3]);
+        assert!(process_txs(config.csv.from_reader(killed), &config)
+            .await
+            .is_err());
+        let checkpoint = super::Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(checkpoint.records, 4);
+
+        // the resumed run skips the records the checkpoint already applied
+        let resumed = process_txs(config.csv.from_reader(data.as_slice()), &config)
+            .await
+            .unwrap();
+        let uninterrupted = process_txs(
+            initialize("csv_files/balance_test.csv").unwrap(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resumed, uninterrupted);
+        assert!(!path.exists());
+
+        // a checkpoint of another version isn't resumed
+        fs::write(&path, b"PECK\x00").unwrap();
+        assert!(super::Checkpoint::load(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn clients_filter() {
+        let config = Config {
+            clients: Some(HashSet::from([17526, 62104])),
+            ..Default::default()
+        };
+        let (output, report) = process_txs(initialize("csv_files/tx.csv").unwrap(), &config)
+            .await
+            .unwrap();
+        assert_eq!(
+            output
+                .iter()
+                .map(|output| output.client.as_str())
+                .collect::<Vec<_>>(),
+            vec!["17526", "62104"]
+        );
+        assert_eq!(report.accounts, 2);
+    }
+
+    #[tokio::test]
+    async fn denylist_file() {
+        let path = TempPath::new("denylist.txt");
+        fs::write(&path, "# under investigation\n2\n").unwrap();
+        let config = Config::from_args([
+            "--exclude-clients-file".to_string(),
+            path.display().to_string(),
+            "csv_files/balance_test.csv".to_string(),
+        ])
+        .unwrap();
+        let mut output = vec![];
+        let report = super::process_inputs(&mut output, &config).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,17.0000,0.0000,17.0000,true\n"
+        );
+        assert_eq!(report.accounts, 1);
+    }
+
+    #[test]
+    fn process_transactions() {
+        let tx = |tx_type, client_id, tx_id, tx_amount| super::Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            tx_amount,
+            timestamp: None,
+            idempotency_key: None,
+            destination_id: None,
+            group_id: None,
+        };
+        let accounts = super::process_transactions(vec![
+            tx(TransactionEnum::Deposit, 2, 1, dec!(5.0)),
+            tx(TransactionEnum::Deposit, 1, 2, dec!(10.0)),
+            tx(TransactionEnum::Withdrawal, 1, 3, dec!(20.0)),
+            tx(TransactionEnum::Dispute, 2, 1, Decimal::ZERO),
+        ]);
+        assert_eq!(
+            accounts
+                .iter()
+                .map(|account| (account.client, account.available, account.held))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, dec!(10.0), Decimal::ZERO),
+                (2, Decimal::ZERO, dec!(5.0))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn minor_units() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1500\ndeposit,1,2,0.5\ndeposit,2,3,10\ndeposit,2,4,0.25\ndeposit,2,5,0.125\n";
+        let run = |decimal_places| async move {
+            let config = Config::from_args([
+                "--minor-units".to_string(),
+                decimal_places,
+                "tx.csv".to_string(),
+            ])
+            .unwrap();
+            process_txs(InputConfig::default().from_reader(data.as_bytes()), &config)
+                .await
+                .unwrap()
+        };
+
+        // yen don't have a minor unit, the fractional deposits are rejected
+        let (output, report) = run("0".to_string()).await;
+        assert_eq!(
+            output
+                .iter()
+                .map(|output| output.total.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1500", "10"]
+        );
+        assert_eq!(report.rejected, 3);
+
+        // cents reject the amounts finer than a cent
+        let (output, report) = run("2".to_string()).await;
+        assert_eq!(
+            output
+                .iter()
+                .map(|output| output.total.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1500.50", "10.25"]
+        );
+        assert_eq!(report.rejected, 1);
+    }
+
+    #[test]
+    fn runtime_fallback() {
+        // the multi-threaded runtime fails to start, e.g. under a thread limit
+        let runtime = super::build_runtime(|| Err(io::Error::other("thread limit"))).unwrap();
+        let (output, _) = runtime
+            .block_on(process_txs(
+                initialize("csv_files/balance_test.csv").unwrap(),
+                &Config::default(),
+            ))
+            .unwrap();
+        assert_eq!(output.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn trace() {
+        let path = TempPath::new("trace.csv");
+        let config = Config {
+            trace: Some(path.to_path_buf()),
+            // the trace is reconciled by client ID, it isn't prefixed
+            client_prefix: "eu-".to_string(),
+            ..Default::default()
+        };
+        let data = "type,client,tx,amount,destination
+deposit,1,1,10.0,
+deposit,2,2,2.5,
+dispute,1,1,,
+withdrawal,1,3,1.0,
+resolve,1,1,,
+transfer,1,4,4.0,2
+";
+        process_txs(InputConfig::default().from_reader(data.as_bytes()), &config)
+            .await
+            .unwrap();
+
+        // the declined withdrawal isn't part of the trace
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "type,client,tx,available,held,total,locked
+deposit,1,1,10.0000,0.0000,10.0000,false
+deposit,2,2,2.5000,0.0000,2.5000,false
+dispute,1,1,0.0000,10.0000,10.0000,false
+resolve,1,1,10.0000,0.0000,10.0000,false
+transfer,1,4,6.0000,0.0000,6.0000,false
+transfer,2,4,6.5000,0.0000,6.5000,false
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn two_pass() {
+        let path = TempPath::new("two_pass.csv");
+        // the deposit without an amount is parsed as a deposit of zero
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,\nwithdrawal,1,3,1.0\n",
+        )
+        .unwrap();
+        let mut config = Config {
+            inputs: vec![path.to_path_buf()],
+            ..Default::default()
+        };
+        let mut output = vec![];
+        let report = super::process_inputs(&mut output, &config).await.unwrap();
+        assert_eq!(report.processed, 3);
+        assert!(!output.is_empty());
+
+        config.two_pass = true;
+        let mut output = vec![];
+        let err = super::process_inputs(&mut output, &config)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "record 2: Transaction ID: 2 has no positive amount for a deposit!"
+        );
+        assert!(output.is_empty());
+
+        config.inputs = vec![PathBuf::from("csv_files/balance_test.csv")];
+        assert_eq!(super::validate(&config).unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn signed_amounts() {
+        // the type column only tells apart the funds movements from the disputes
+        let data = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,-2.5
+withdrawal,2,3,4.0
+deposit,2,4,-1.0
+dispute,1,2
+";
+        let input = super::Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+            .signed_amounts(true);
+        let (output, report) = process_txs(input, &Config::default()).await.unwrap();
+        assert_eq!(
+            output
+                .iter()
+                .map(|output| (output.available.as_str(), output.held.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("5.0000", "2.5000"), ("3.0000", "0.0000")]
+        );
+        // the negative deposit is disputed like a withdrawal
+        assert_eq!((report.processed, report.rejected), (5, 0));
+    }
+
+    #[tokio::test]
+    async fn amounts_in_cents() {
+        let data = "type,client,tx,amount
+deposit,1,1,1050
+withdrawal,1,2,299
+deposit,2,3,7
+deposit,2,4,10.5
+";
+        let input = super::Input::csv(InputConfig::default().from_reader(data.as_bytes()))
+            .amounts_in_cents(true)
+            .strict(false);
+        let (output, report) = process_txs(input, &Config::default()).await.unwrap();
+        assert_eq!(
+            output
+                .iter()
+                .map(|output| output.total.as_str())
+                .collect::<Vec<_>>(),
+            vec!["7.5100", "0.0700"]
+        );
+        // a fractional amount of cents is malformed
+        assert_eq!((report.processed, report.skipped), (3, 1));
+    }
+
+    #[tokio::test]
+    async fn buffered_output() {
+        // the accounts outgrow the buffer several times
+        let data: String = std::iter::once("type,client,tx,amount\n".to_string())
+            .chain((1..=20).map(|client| format!("deposit,{client},{client},1.5\n")))
+            .collect();
+        let mut unbuffered = vec![];
+        super::process_reader(
+            InputConfig::default().from_reader(data.as_bytes()),
+            &mut unbuffered,
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+
+        // the buffer is flushed once every account is written
+        let mut buffered = io::BufWriter::with_capacity(64, vec![]);
+        super::process_reader(
+            InputConfig::default().from_reader(data.as_bytes()),
+            &mut buffered,
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        assert!(buffered.buffer().is_empty());
+        assert_eq!(buffered.get_ref(), &unbuffered);
+    }
+
+    #[tokio::test]
+    async fn process_readers() {
+        let first: Box<dyn Read> = Box::new(io::Cursor::new(
+            "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n",
+        ));
+        // the second reader has it's own header, with another column order
+        let second: Box<dyn Read> = Box::new(io::Cursor::new(
+            "client,type,tx,amount\n1,withdrawal,3,2.5\n2,dispute,2,\n",
+        ));
+        let mut output = vec![];
+        let report = super::process_readers(vec![first, second], &mut output, &Config::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked
+1,7.5000,0.0000,7.5000,false
+2,0.0000,5.0000,5.0000,false
+"
+        );
+        assert_eq!((report.processed, report.rejected), (4, 0));
+        assert!(
+            super::process_readers(vec![], io::sink(), &Config::default())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn columns() {
+        let run = |columns| async move {
+            let config = Config {
+                columns,
+                ..Default::default()
+            };
+            let mut output = vec![];
+            super::process_reader(
+                initialize("csv_files/balance_test.csv").unwrap(),
+                &mut output,
+                &config,
+            )
+            .await
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            run(vec![super::Column::Available]).await,
+            "client,available\n1,17.0000\n2,9.0000\n"
+        );
+        // the columns are written in the requested order
+        assert_eq!(
+            run(vec![super::Column::Locked, super::Column::Held]).await,
+            "client,locked,held\n1,true,0.0000\n2,false,100.0000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn as_of() {
+        let data = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawal,1,3,4.0
+dispute,2,2
+deposit,2,4,1.0
+resolve,2,2
+withdrawal,1,5,1.0
+";
+        let run = |data: String, as_of| async move {
+            let config = Config {
+                as_of,
+                ..Default::default()
+            };
+            let mut output = vec![];
+            super::process_reader(
+                InputConfig::default().from_reader(data.as_bytes()),
+                &mut output,
+                &config,
+            )
+            .await
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // the state as of transaction 3 includes the dispute referencing an
+        // earlier transaction, it stops at transaction 4
+        let prefix = data.lines().take(5).collect::<Vec<_>>().join("\n");
+        let data = data.to_string();
+        assert_eq!(run(data.clone(), Some(3)).await, run(prefix, None).await);
+        assert_ne!(
+            run(data.clone(), Some(3)).await,
+            run(data.clone(), None).await
+        );
+        assert_eq!(run(data.clone(), Some(5)).await, run(data, None).await);
+    }
+
+    #[tokio::test]
+    async fn end_client() {
+        let data = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,50.0
+end_client,2,3,
+withdrawal,1,4,1.0
+deposit,2,5,1.0
+";
+        let run = |config: Config| async move {
+            let mut output = vec![];
+            let report = super::process_reader(
+                InputConfig::default().from_reader(data.as_bytes()),
+                &mut output,
+                &config,
+            )
+            .await
+            .unwrap();
+            (String::from_utf8(output).unwrap(), report)
+        };
+
+        // the ended client keeps it's place among the others, its later
+        // deposit is rejected
+        let (output, report) = run(Config::default()).await;
+        assert_eq!(
+            output,
+            "client,available,held,total,locked
+1,9.0000,0.0000,9.0000,false
+2,50.0000,0.0000,50.0000,false
+"
+        );
+        assert_eq!(report.rejected, 1);
+        assert_eq!(report.accounts, 2);
+
+        let (output, _) = run(Config {
+            sort_by: SortBy::Total,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(
+            output,
+            "client,available,held,total,locked
+2,50.0000,0.0000,50.0000,false
+1,9.0000,0.0000,9.0000,false
+"
+        );
+        let (output, _) = run(Config {
+            output_format: OutputFormat::Summary,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(output, "clients=2 locked=0 total=59.0000\n");
+    }
+
+    #[tokio::test]
+    async fn end_client_resume() {
+        let path = TempPath::new("end_client_resume.ckpt");
+        let data = "type,client,tx,amount
+deposit,1,1,10
+deposit,2,2,5
+end_client,2,3,
+withdrawal,1,4,100
+deposit,1,5,1
+deposit,2,6,1
+";
+        let config = Config {
+            checkpoint: Some(path.to_path_buf()),
+            checkpoint_every: 3,
+            ..Default::default()
+        };
+
+        // the run is aborted after the checkpoint that follows the marker
+        let aborted = Config {
+            max_errors: Some(1),
+            ..config.clone()
+        };
+        let mut output = vec![];
+        assert!(super::process_reader(
+            InputConfig::default().from_reader(data.as_bytes()),
+            &mut output,
+            &aborted,
+        )
+        .await
+        .is_err());
+        assert!(output.is_empty());
+
+        // the resumed run still outputs the ended client and rejects its later
+        // deposit
+        let report = super::process_reader(
+            InputConfig::default().from_reader(data.as_bytes()),
+            &mut output,
+            &config,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked
+1,11.0000,0.0000,11.0000,false
+2,5.0000,0.0000,5.0000,false
+"
+        );
+        assert_eq!(report.rejected, 2);
+        assert_eq!(report.accounts, 2);
+    }
+
+    /// Reader failing once `data` was read
+    struct FailingReader {
+        data: &'static [u8],
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.data.is_empty() {
+                return Err(io::Error::other("connection reset"));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[tokio::test]
+    async fn read_error() {
+        let run = |strict| async move {
+            let reader = FailingReader {
+                data: b"type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n",
+            };
+            let config = Config {
+                strict,
+                ..Default::default()
+            };
+            let mut output = vec![];
+            let result = super::process_reader(
+                InputConfig::default().from_reader(reader),
+                &mut output,
+                &config,
+            )
+            .await;
+            (String::from_utf8(output).unwrap(), result.unwrap_err())
+        };
+
+        // the balances computed before the failure are written
+        let (output, err) = run(false).await;
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n"
+        );
+        assert_eq!(
+            err.to_string(),
+            "The input failed after 2 records, the balances include them"
+        );
+        assert_eq!(err.root_cause().to_string(), "connection reset");
+        // a strict run writes nothing
+        let (output, err) = run(true).await;
+        assert!(output.is_empty());
+        assert_eq!(err.root_cause().to_string(), "connection reset");
+    }
+
+    #[tokio::test]
+    async fn with_counts() {
+        let data = "type,client,tx,amount
+deposit,1,1,10.0
+dispute,1,1
+resolve,1,1
+withdrawal,1,2,4.0
+withdrawal,1,3,50.0
+deposit,2,4,1.0
+";
+        let config =
+            Config::from_args(["--with-counts".to_string(), "tx.csv".to_string()]).unwrap();
+        let mut output = vec![];
+        super::process_reader(
+            InputConfig::default().from_reader(data.as_bytes()),
+            &mut output,
+            &config,
+        )
+        .await
+        .unwrap();
+        // the disputes count, not the rejected withdrawal
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,num_transactions
+1,6.0000,0.0000,6.0000,false,4
+2,1.0000,0.0000,1.0000,false,1
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_prefix() {
+        let config = Config {
+            client_prefix: "eu-".to_string(),
+            ..Default::default()
+        };
+        let mut output = vec![];
+        super::process_reader(
+            initialize("csv_files/balance_test.csv").unwrap(),
+            &mut output,
+            &config,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\neu-1,17.0000,0.0000,17.0000,true\neu-2,9.0000,100.0000,109.0000,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn decimal_separator() {
+        let config = Config {
+            decimal_separator: ',',
+            ..Default::default()
+        };
+        let mut output = vec![];
+        super::process_reader(
+            initialize("csv_files/balance_test.csv").unwrap(),
+            &mut output,
+            &config,
+        )
+        .await
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output.lines().nth(1),
+            Some("1,\"17,0000\",\"0,0000\",\"17,0000\",true")
+        );
+
+        // the quoted amounts are read back as single fields
+        let records = ReaderBuilder::new()
+            .from_reader(output.as_bytes())
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(&records[1][3], "109,0000");
     }
 }
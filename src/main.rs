@@ -1,9 +1,22 @@
 use anyhow::Result;
-use payment_engine::{initialize, process_txs};
+use payment_engine::{buffered_stdout, initialize, process_inputs, runtime, Config, FileStats};
+use std::process::ExitCode;
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     env_logger::init();
-    process_txs(initialize()?).await?;
-    Ok(())
+    let config = Config::from_args(std::env::args().skip(1))?;
+    if config.check {
+        print!("{}", FileStats::collect(initialize(&config)?)?);
+        return Ok(ExitCode::SUCCESS);
+    }
+    // a runtime that can't be started is reported like any other error
+    runtime()?.block_on(run(&config))
+}
+
+/// Processes the input files and prints the account's balances, the exit code
+/// tells apart a run that left out some transactions
+async fn run(config: &Config) -> Result<ExitCode> {
+    let report = process_inputs(buffered_stdout(), config).await?;
+    log::info!("{report}");
+    Ok(report.outcome().into())
 }
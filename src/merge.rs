@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Result};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, RecvError, SendError, SyncSender, TryRecvError},
+        Arc,
+    },
+    thread,
+};
+use tokio::sync::Notify;
+
+use crate::{archive, config::Config, input::Input, transaction::Transaction};
+
+/// Number of transactions a reader can parse ahead of the merge
+const READ_AHEAD: usize = 1024;
+
+/// Message sent by a file reader
+enum Read {
+    /// Next transaction of the file
+    Tx(Transaction),
+    /// The file is over
+    End { skipped: u64 },
+}
+
+/// Transactions read from a single file
+struct File {
+    /// transactions parsed by the reader thread
+    rx: Receiver<Result<Read>>,
+    /// next transaction and its position in the merged order
+    head: Option<(u64, Transaction)>,
+    /// timestamp of the last transaction, used for those without one
+    timestamp: u64,
+    /// flag indicating if the file is over
+    done: bool,
+    /// failure of the reader, returned in place of the next transaction
+    error: Option<anyhow::Error>,
+}
+
+impl File {
+    fn new(rx: Receiver<Result<Read>>) -> Self {
+        Self {
+            rx,
+            head: None,
+            timestamp: 0,
+            done: false,
+            error: None,
+        }
+    }
+
+    /// Returns `true` once the next message of the reader was received
+    fn ready(&self) -> bool {
+        self.head.is_some() || self.done || self.error.is_some()
+    }
+
+    /// Stores a message of the reader, the skipped records of a finished
+    /// file are added to `skipped`
+    fn receive(&mut self, read: Result<Result<Read>, RecvError>, skipped: &mut u64) {
+        match read {
+            Ok(Ok(Read::Tx(tx))) => {
+                self.timestamp = tx.timestamp.unwrap_or(self.timestamp);
+                self.head = Some((self.timestamp, tx));
+            }
+            Ok(Ok(Read::End {
+                skipped: file_skipped,
+            })) => {
+                *skipped += file_skipped;
+                self.done = true;
+            }
+            Ok(Err(err)) => self.error = Some(err),
+            Err(_) => self.error = Some(anyhow!("A file reader stopped unexpectedly!")),
+        }
+    }
+}
+
+/// Sending half of a file's channel, the merge waiting for the reader is
+/// woken up after every message
+struct Sender {
+    tx: SyncSender<Result<Read>>,
+    notify: Arc<Notify>,
+}
+
+impl Sender {
+    fn send(&self, read: Result<Read>) -> Result<(), SendError<Result<Read>>> {
+        let sent = self.tx.send(read);
+        self.notify.notify_one();
+        sent
+    }
+}
+
+/// Wakes the merge up once it's dropped, after the sender it guards so the
+/// merge sees the closed channel, even when the reader panics
+struct Closed(Arc<Notify>);
+
+impl Drop for Closed {
+    fn drop(&mut self) {
+        self.0.notify_one();
+    }
+}
+
+/// Returns the channel of a file read by a reader thread
+fn channel(notify: &Arc<Notify>) -> (Sender, File) {
+    let (tx, rx) = mpsc::sync_channel(READ_AHEAD);
+    let notify = notify.clone();
+    (Sender { tx, notify }, File::new(rx))
+}
+
+/// Reads several files concurrently, one reader thread per file, and merges
+/// their transactions by timestamp.
+///
+/// A transaction without a timestamp takes the one of the previous transaction
+/// of its file, ties are ordered by file so files without timestamps are read
+/// one after the other
+pub(crate) struct Merge {
+    files: Vec<File>,
+    /// notified by the readers after every message
+    notify: Arc<Notify>,
+    /// number of malformed records skipped by the readers
+    pub(crate) skipped: u64,
+}
+
+impl Merge {
+    /// Spawns a reader thread for every file
+    pub(crate) fn spawn(paths: &[PathBuf], config: &Config) -> Self {
+        let notify = Arc::new(Notify::new());
+        let files = paths
+            .iter()
+            .map(|path| {
+                let (tx, file) = channel(&notify);
+                let config = config.clone();
+                let path = path.clone();
+                thread::spawn(move || {
+                    let _closed = Closed(tx.notify.clone());
+                    if let Err(err) = read_file(&path, &config, &tx) {
+                        let _ = tx.send(Err(err));
+                    }
+                });
+                file
+            })
+            .collect();
+        Self {
+            files,
+            notify,
+            skipped: 0,
+        }
+    }
+
+    /// Waits until the next transaction can be returned without blocking, the
+    /// processing is async so it must not block on the reader threads
+    pub(crate) async fn ready(&mut self) {
+        let notify = self.notify.clone();
+        loop {
+            // a reader's message sent meanwhile leaves a permit behind, it
+            // isn't missed
+            let notified = notify.notified();
+            if self.poll() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Receives the messages already sent by the readers, returns `true` once
+    /// every file is ready
+    fn poll(&mut self) -> bool {
+        let mut ready = true;
+        for file in self.files.iter_mut().filter(|file| !file.ready()) {
+            match file.rx.try_recv() {
+                Ok(read) => file.receive(Ok(read), &mut self.skipped),
+                Err(TryRecvError::Empty) => ready = false,
+                Err(TryRecvError::Disconnected) => file.receive(Err(RecvError), &mut self.skipped),
+            }
+        }
+        ready
+    }
+
+    /// Returns the next transaction in the merged order, `None` once every
+    /// file is over. It blocks on the readers unless it's `ready`
+    pub(crate) fn next_tx(&mut self) -> Result<Option<Transaction>> {
+        for file in self.files.iter_mut().filter(|file| !file.ready()) {
+            file.receive(file.rx.recv(), &mut self.skipped);
+        }
+        if let Some(file) = self.files.iter_mut().find(|file| file.error.is_some()) {
+            file.done = true;
+            return Err(file.error.take().expect("the file failed"));
+        }
+
+        // the first file wins the ties
+        let next = self
+            .files
+            .iter_mut()
+            .filter(|file| file.head.is_some())
+            .min_by_key(|file| file.head.as_ref().map(|(timestamp, _)| *timestamp));
+        Ok(next.and_then(|file| file.head.take()).map(|(_, tx)| tx))
+    }
+}
+
+/// Sends every transaction of a file to the merge, the files of an archive
+/// are sent one after the other
+fn read_file(path: &Path, config: &Config, tx: &Sender) -> Result<()> {
+    let mut skipped = 0;
+    if archive::is_archive(path) {
+        archive::read_entries(path, config, |mut input| {
+            let sent = send_txs(&mut input, tx)?;
+            skipped += input.skipped();
+            Ok(sent)
+        })?;
+    } else {
+        let mut input = crate::initialize_file(path, config)?;
+        send_txs(&mut input, tx)?;
+        skipped = input.skipped();
+    }
+    let _ = tx.send(Ok(Read::End { skipped }));
+    Ok(())
+}
+
+/// Sends every transaction of the input to the merge, returns `false` if the
+/// merge was dropped
+fn send_txs<R: io::Read>(input: &mut Input<R>, tx: &Sender) -> Result<bool> {
+    while let Some(transaction) = input.read_tx()? {
+        // the remaining transactions aren't needed
+        if tx.send(Ok(Read::Tx(transaction))).is_err() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::TransactionEnum;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn ready_without_blocking() {
+        let notify = Arc::new(Notify::new());
+        let (tx, file) = channel(&notify);
+        let mut merge = Merge {
+            files: vec![file],
+            notify,
+            skipped: 0,
+        };
+        let deposit = Transaction {
+            tx_type: TransactionEnum::Deposit,
+            client_id: 1,
+            tx_id: 1,
+            tx_amount: dec!(10),
+            timestamp: None,
+            idempotency_key: None,
+            destination_id: None,
+            group_id: None,
+        };
+        // the single-threaded runtime only sends once the merge waits, a
+        // blocking wait would never return
+        let send = async {
+            tokio::task::yield_now().await;
+            tx.send(Ok(Read::Tx(deposit.clone()))).unwrap();
+            drop(tx);
+        };
+        tokio::join!(merge.ready(), send);
+        assert_eq!(merge.next_tx().unwrap(), Some(deposit));
+
+        // the closed channel is reported once the merge is ready
+        merge.ready().await;
+        assert!(merge.next_tx().is_err());
+    }
+}
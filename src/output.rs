@@ -0,0 +1,490 @@
+use anyhow::{bail, Context, Result};
+use csv::{ByteRecord, Writer};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{
+    io::{self, BufWriter, Read, Stdout, Write},
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+use crate::{
+    account::{format_scaled, Account},
+    config::Config,
+};
+
+/// Capacity of the buffer of the standard output, the CSV writer's own buffer
+/// is flushed to it in small chunks
+const STDOUT_BUFFER: usize = 64 * 1024;
+
+/// Returns the standard output buffered for the accounts.
+///
+/// The standard output is line buffered, every chunk of the CSV writer is a
+/// write syscall, the buffer batches them. It's flushed by the writer once every
+/// account is written
+pub fn buffered_stdout() -> BufWriter<Stdout> {
+    BufWriter::with_capacity(STDOUT_BUFFER, io::stdout())
+}
+
+/// Magic bytes starting the accounts encoded with bincode, followed by the
+/// version of their layout. Accounts encoded before the format was versioned
+/// start with their number instead
+const BINCODE_MAGIC: [u8; 4] = *b"PEAC";
+
+/// Version of the layout of the accounts encoded with bincode
+const BINCODE_VERSION: u8 = 1;
+
+/// Handle to a writer shared by several output consumers, e.g. the workers
+/// writing their rows to the same trace.
+///
+/// Every handle buffers what it writes until it's flushed, then passes it on
+/// at once while holding a lock. A consumer flushing after every complete
+/// record, e.g. a CSV record with a quoted line break or an encoded value, is
+/// never interleaved with the others. A clone is a new handle to the same
+/// writer, what's left is written when a handle is dropped
+pub struct SharedWriter<W: Write> {
+    /// writer shared by every handle
+    inner: Arc<Mutex<W>>,
+    /// bytes written since the handle was last flushed
+    pending: Vec<u8>,
+}
+
+impl<W: Write> SharedWriter<W> {
+    /// Returns the first handle to the writer
+    pub fn new(writer: W) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(writer)),
+            pending: vec![],
+        }
+    }
+
+    /// Flushes the handle returning the writer, `None` while other handles
+    /// still exist
+    pub fn into_inner(self) -> Option<W> {
+        let inner = Arc::clone(&self.inner);
+        drop(self);
+        let inner = Arc::into_inner(inner)?;
+        Some(inner.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl<W: Write> Clone for SharedWriter<W> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            pending: vec![],
+        }
+    }
+}
+
+impl<W: Write> Write for SharedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Passes on every byte written since the last flush, a handle that
+    /// panicked while writing left at most a partial record
+    fn flush(&mut self) -> io::Result<()> {
+        let mut writer = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if !self.pending.is_empty() {
+            writer.write_all(&self.pending)?;
+            self.pending.clear();
+        }
+        writer.flush()
+    }
+}
+
+impl<W: Write> Drop for SharedWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Format of the account's balances written to the output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// CSV records with a header
+    #[default]
+    Csv,
+    /// The accounts encoded with bincode as a single `Vec<Account>`
+    Bincode,
+    /// A single line counting the accounts and summing their balances, e.g.
+    /// `clients=3 locked=1 total=126.0000`
+    Summary,
+}
+
+/// Column of the account's balances written after the client ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    /// Available balance
+    Available,
+    /// Held balance
+    Held,
+    /// Total balance
+    Total,
+    /// Flag indicating if the account is frozen
+    Locked,
+    /// Number of transactions applied to the account
+    Transactions,
+}
+
+impl Column {
+    /// Columns written by default, in order
+    pub const DEFAULT: [Column; 4] = [
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+    ];
+
+    /// Every column that can be selected
+    pub const ALL: [Column; 5] = [
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+        Column::Transactions,
+    ];
+
+    /// Returns the header of the column
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Total => "total",
+            Column::Locked => "locked",
+            Column::Transactions => "num_transactions",
+        }
+    }
+}
+
+/// Writes every account received from the ProcessTransaction task to `writer`
+///
+/// If the writer is closed (e.g. the output is piped to `head`) writing stops
+/// without an error
+pub(crate) async fn write_accounts<W: Write>(
+    rx_result: &mut UnboundedReceiver<Account>,
+    writer: W,
+    config: &Config,
+) {
+    match config.output_format {
+        OutputFormat::Csv => write_csv(rx_result, writer, config).await,
+        OutputFormat::Bincode => write_bincode(rx_result, writer).await,
+        OutputFormat::Summary => write_summary(rx_result, writer, config).await,
+    }
+}
+
+/// Writes the accounts of a snapshot to `writer` like the final ones
+pub(crate) async fn write_snapshot<W: Write>(accounts: Vec<Account>, writer: W, config: &Config) {
+    let (tx_result, mut rx_result) = mpsc::unbounded_channel();
+    for account in accounts {
+        let _ = tx_result.send(account);
+    }
+    drop(tx_result);
+    write_accounts(&mut rx_result, writer, config).await;
+}
+
+/// Writes the accounts as CSV records
+async fn write_csv<W: Write>(
+    rx_result: &mut UnboundedReceiver<Account>,
+    writer: W,
+    config: &Config,
+) {
+    // create a CSV writer
+    let mut writer = Writer::from_writer(writer);
+    // Write the header values to the record to printout in the output, the
+    // client ID is followed by the selected columns
+    let header = config.columns.iter().map(|column| column.name());
+    if let Err(err) = writer.write_record(std::iter::once("client").chain(header)) {
+        if closed(&err) {
+            log::debug!("Output was closed, no records are written");
+            return;
+        }
+        log::error!("Error in writing the header! \n {err}")
+    }
+
+    // write every record received from ProcessTransaction task to the writer
+    while let Some(account) = rx_result.recv().await {
+        // a field holding the delimiter, e.g. a comma decimal separator, is quoted
+        let mut info = account.get_info(config, &config.columns);
+        // only the output identifies the clients by the prefix
+        info[0].insert_str(0, &config.client_prefix);
+        let record = ByteRecord::from(info);
+        if let Err(err) = writer.write_byte_record(&record) {
+            if closed(&err) {
+                log::debug!("Output was closed, no more records are written");
+                break;
+            }
+            log::error!("Error in writing records! \n {err}")
+        }
+    }
+    // records are buffered by the writer, a closed output is usually only detected here
+    match writer.flush() {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+            log::error!("Error in writing records! \n {err}")
+        }
+        _ => {}
+    }
+}
+
+/// Returns `true` when the error is the output being closed, e.g. a pipe to
+/// `head`
+fn closed(err: &csv::Error) -> bool {
+    matches!(err.kind(), csv::ErrorKind::Io(err) if err.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Writes the accounts encoded with bincode, the whole vector is encoded at
+/// once so every account is received first
+async fn write_bincode<W: Write>(rx_result: &mut UnboundedReceiver<Account>, mut writer: W) {
+    let mut accounts = vec![];
+    while let Some(account) = rx_result.recv().await {
+        accounts.push(account);
+    }
+    let written = writer
+        .write_all(&BINCODE_MAGIC)
+        .and_then(|()| writer.write_all(&[BINCODE_VERSION]))
+        .map_err(bincode::Error::from)
+        .and_then(|()| bincode::serialize_into(&mut writer, &accounts))
+        .and_then(|()| Ok(writer.flush()?));
+    match written.map_err(|err| *err) {
+        Err(bincode::ErrorKind::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => {
+            log::debug!("Output was closed, the accounts were not written");
+        }
+        Err(err) => log::error!("Error in writing records! \n {err}"),
+        Ok(()) => {}
+    }
+}
+
+/// Writes a single line with the number of accounts, of the locked ones and
+/// the sum of their total balances, every account is received first
+async fn write_summary<W: Write>(
+    rx_result: &mut UnboundedReceiver<Account>,
+    mut writer: W,
+    config: &Config,
+) {
+    let (mut clients, mut locked, mut total) = (0, 0, Decimal::ZERO);
+    while let Some(account) = rx_result.recv().await {
+        clients += 1;
+        locked += usize::from(account.locked);
+        total += account.total;
+    }
+    let total = format_scaled(total, config.policy.decimal_places);
+    let written = writeln!(writer, "clients={clients} locked={locked} total={total}")
+        .and_then(|()| writer.flush());
+    match written {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+            log::debug!("Output was closed, the summary was not written");
+        }
+        Err(err) => log::error!("Error in writing the summary! \n {err}"),
+        Ok(()) => {}
+    }
+}
+
+/// Layout of the accounts encoded before the format was versioned, they had
+/// no number of transactions
+#[derive(Deserialize)]
+struct LegacyAccount {
+    client: u16,
+    #[serde(with = "rust_decimal::serde::str")]
+    available: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    held: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    total: Decimal,
+    locked: bool,
+    last_tx_id: u32,
+}
+
+/// Decodes the accounts written with [`OutputFormat::Bincode`], including
+/// the ones written before the format was versioned, their number of
+/// transactions is zero
+pub fn decode_accounts<R: Read>(mut reader: R) -> Result<Vec<Account>> {
+    let mut magic = [0; 4];
+    reader
+        .read_exact(&mut magic)
+        .context("Unable to decode the accounts")?;
+    if magic != BINCODE_MAGIC {
+        let legacy: Vec<LegacyAccount> = bincode::deserialize_from(magic.as_slice().chain(reader))
+            .context("Unable to decode the accounts")?;
+        return Ok(legacy
+            .into_iter()
+            .map(|account| Account {
+                client: account.client,
+                available: account.available,
+                held: account.held,
+                total: account.total,
+                locked: account.locked,
+                last_tx_id: account.last_tx_id,
+                transactions: 0,
+            })
+            .collect());
+    }
+    let mut version = [0];
+    reader
+        .read_exact(&mut version)
+        .context("Unable to decode the accounts")?;
+    if version[0] != BINCODE_VERSION {
+        bail!(
+            "Unable to decode the accounts, version {} isn't supported",
+            version[0]
+        );
+    }
+    bincode::deserialize_from(reader).context("Unable to decode the accounts")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_accounts, write_accounts, OutputFormat, SharedWriter};
+    use crate::{account::Account, config::Config};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use serde::Deserialize;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn locked_round_trip() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Output {
+            client: u16,
+            total: Decimal,
+            locked: bool,
+        }
+
+        let (tx_result, mut rx_result) = mpsc::unbounded_channel();
+        for (client, locked) in [(1, true), (2, false)] {
+            tx_result
+                .send(Account {
+                    client,
+                    available: dec!(1.5),
+                    held: Decimal::ZERO,
+                    total: dec!(1.5),
+                    locked,
+                    last_tx_id: 1,
+                    transactions: 1,
+                })
+                .unwrap();
+        }
+        drop(tx_result);
+        let mut written = vec![];
+        write_accounts(&mut rx_result, &mut written, &Config::default()).await;
+
+        // the flag is written lowercase, as parsed by a bool field
+        let csv = String::from_utf8(written).unwrap();
+        assert!(csv.ends_with("1,1.5000,0.0000,1.5000,true\n2,1.5000,0.0000,1.5000,false\n"));
+        let read: Vec<Output> = csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            read,
+            vec![
+                Output {
+                    client: 1,
+                    total: dec!(1.5),
+                    locked: true,
+                },
+                Output {
+                    client: 2,
+                    total: dec!(1.5),
+                    locked: false,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn bincode_round_trip() {
+        let accounts = vec![
+            Account {
+                client: 1,
+                available: dec!(1.5),
+                held: dec!(0.0),
+                total: dec!(1.5),
+                locked: false,
+                last_tx_id: 3,
+                transactions: 2,
+            },
+            Account {
+                client: 2,
+                available: dec!(-2.1234),
+                held: dec!(10),
+                total: dec!(7.8766),
+                locked: true,
+                last_tx_id: 4,
+                transactions: 5,
+            },
+        ];
+        let (tx_result, mut rx_result) = mpsc::unbounded_channel();
+        for account in &accounts {
+            tx_result.send(*account).unwrap();
+        }
+        drop(tx_result);
+
+        let mut encoded = vec![];
+        let config = Config {
+            output_format: OutputFormat::Bincode,
+            ..Default::default()
+        };
+        write_accounts(&mut rx_result, &mut encoded, &config).await;
+        assert_eq!(decode_accounts(encoded.as_slice()).unwrap(), accounts);
+        assert!(decode_accounts(&encoded[..encoded.len() - 1]).is_err());
+
+        // accounts encoded before the format was versioned, without the
+        // number of transactions
+        let legacy = bincode::serialize(&vec![(1u16, "1.5", "0.0", "1.5", false, 3u32)]).unwrap();
+        let decoded = decode_accounts(legacy.as_slice()).unwrap();
+        assert_eq!(
+            decoded,
+            vec![Account {
+                transactions: 0,
+                ..accounts[0]
+            }]
+        );
+
+        // a newer version isn't guessed
+        encoded[4] += 1;
+        assert!(decode_accounts(encoded.as_slice()).is_err());
+    }
+
+    #[test]
+    fn shared_writer() {
+        let writer = SharedWriter::new(vec![]);
+        // the CSV writer passes on it's buffer in chunks splitting the records,
+        // the quoted field holds a line break
+        let handles: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| {
+                let mut writer = csv::Writer::from_writer(writer.clone());
+                std::thread::spawn(move || {
+                    for i in 0..2000 {
+                        let field = format!("{}\n{}", name.repeat(100), name.repeat(100));
+                        writer.write_record([field, i.to_string()]).unwrap();
+                        writer.flush().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let output = writer.into_inner().unwrap();
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(output.as_slice());
+        let mut counts = [0, 0];
+        for record in reader.records() {
+            let record = record.unwrap();
+            let index = match &record[0] {
+                a if a == format!("{}\n{}", "a".repeat(100), "a".repeat(100)) => 0,
+                b if b == format!("{}\n{}", "b".repeat(100), "b".repeat(100)) => 1,
+                _ => panic!("interleaved record {record:?}"),
+            };
+            // the records of a writer keep their order
+            assert_eq!(&record[1], counts[index].to_string());
+            counts[index] += 1;
+        }
+        assert_eq!(counts, [2000, 2000]);
+    }
+}
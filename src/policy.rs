@@ -0,0 +1,107 @@
+use crate::account::DECIMAL_PLACES;
+
+/// How a dispute referencing a withdrawal is applied
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalDispute {
+    /// The withdrawn amount is held from the available funds like a deposit,
+    /// the dispute is rejected if they aren't sufficient
+    #[default]
+    Hold,
+    /// Withdrawals can't be disputed
+    Reject,
+    /// The withdrawn amount may be returned, it's held on top of the balance
+    /// increasing the total. A resolve drops the held funds and a chargeback
+    /// releases them to the available balance
+    Credit,
+}
+
+/// How an amount with more decimal places than configured is applied
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    /// The amount is rounded to the decimal places and the rounded value is applied,
+    /// e.g. `0.00001` is a deposit of `0.0000`
+    #[default]
+    Round,
+    /// The transaction is rejected
+    Reject,
+}
+
+/// Scope in which the deposit and withdrawal IDs must be increasing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxOrder {
+    /// Every ID is greater than the previous one of any client
+    Global,
+    /// Every ID is greater than the previous one of the same client
+    PerClient,
+}
+
+/// Rules applied when processing transactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// When a dispute exceeds the available funds, hold only what's available
+    /// and record the shortfall instead of rejecting the dispute
+    pub partial_hold: bool,
+    /// Reject deposits and withdrawals reusing a transaction ID already seen
+    /// for any client
+    pub unique_tx_ids: bool,
+    /// When an account is unfrozen release its held funds to the available
+    /// balance, dropping the open disputes, instead of keeping them held
+    pub release_held_on_unfreeze: bool,
+    /// How a dispute referencing a withdrawal is applied
+    pub withdrawal_disputes: WithdrawalDispute,
+    /// Reject deposits and withdrawals whose ID isn't greater than the previous
+    /// one, `None` doesn't check the order
+    pub ordered_tx_ids: Option<TxOrder>,
+    /// Number of chargebacks freezing an account, defaults to the first one
+    pub freeze_after: u32,
+    /// Keep applying deposits to frozen accounts, every other transaction is
+    /// still rejected
+    pub allow_frozen_deposits: bool,
+    /// Maximum number of distinct clients, the transactions of any further
+    /// client are rejected. `None` doesn't limit them
+    pub max_clients: Option<usize>,
+    /// How an amount with more decimal places than configured is applied
+    pub precision: Precision,
+    /// Number of decimal places of the amounts, the minor unit of the
+    /// currency, e.g. `2` for cents or `0` for yen
+    pub decimal_places: u32,
+    /// Maximum number of deposits and withdrawals kept in a client's history,
+    /// the oldest ones that aren't disputed are evicted and can't be disputed
+    /// anymore. `None` keeps every transaction
+    pub max_tx_per_client: Option<usize>,
+    /// Reject withdrawals that would leave exactly zero available, a positive
+    /// balance must remain
+    pub deny_zero_balance: bool,
+    /// Apply deposits with a negative amount as corrections subtracting from
+    /// the available balance instead of rejecting them
+    pub correction_deposits: bool,
+    /// Only a deposit creates a client, the other transactions of an unseen
+    /// client are rejected instead of creating an empty account
+    pub require_account_creation: bool,
+    /// Maximum number of transactions applied after a deposit or withdrawal
+    /// for it to be disputed, a later dispute is stale and rejected. `None`
+    /// doesn't limit them
+    pub dispute_window: Option<u64>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            partial_hold: false,
+            unique_tx_ids: false,
+            release_held_on_unfreeze: false,
+            withdrawal_disputes: WithdrawalDispute::default(),
+            ordered_tx_ids: None,
+            freeze_after: 1,
+            allow_frozen_deposits: false,
+            max_clients: None,
+            precision: Precision::default(),
+            decimal_places: DECIMAL_PLACES,
+            max_tx_per_client: None,
+            deny_zero_balance: false,
+            correction_deposits: false,
+            require_account_creation: false,
+            dispute_window: None,
+        }
+    }
+}
@@ -1,86 +1,612 @@
-use csv::ByteRecord;
-use std::collections::HashMap;
-use tokio::sync::{mpsc, mpsc::error::TryRecvError};
+use anyhow::{anyhow, bail, Context, Result};
+use csv::Writer;
+use rust_decimal::Decimal;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Write},
+    iter,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{
+    account::Account,
+    checkpoint::Checkpoint,
+    config::{Config, SortBy},
+    engine::{MovedClient, PaymentEngine},
+    event::{Event, LogFormat},
+    histogram::Histogram,
+    output::{Column, SharedWriter},
+    transaction::{Transaction, TransactionEnum},
+};
+
+/// Columns of the trace, the balances of a client after a transaction
+const TRACE_HEADER: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "available",
+    "held",
+    "total",
+    "locked",
+];
+
+/// Creates the file of the trace
+fn create_trace(path: &Path) -> Result<File> {
+    File::create(path).with_context(|| format!("Unable to create trace {}", path.display()))
+}
+
+/// Message sent to the ProcessTransaction task
+#[derive(Debug)]
+pub(crate) enum Command {
+    /// Transaction to apply
+    Tx(Transaction),
+    /// Save a checkpoint of the accounts once `records` input records were
+    /// read, `done` reports when it's saved
+    Checkpoint {
+        path: PathBuf,
+        records: u64,
+        done: oneshot::Sender<Result<()>>,
+    },
+    /// Send back the accounts to output once the transactions received so
+    /// far are applied
+    Snapshot { done: oneshot::Sender<Vec<Account>> },
+    /// Remove a client once the transactions received so far are applied,
+    /// `done` receives it to insert it in another worker
+    Take {
+        client_id: u16,
+        done: oneshot::Sender<MovedClient>,
+    },
+    /// Insert a client taken from another worker
+    Insert(Box<MovedClient>),
+}
 
-use crate::{client::Client, transaction::Transaction};
 /// This task processes transactions, for every transaction received
 /// it sorts them by client id and performs actions based on transaction type for every client id.
 struct ProcessTransactionsTask {
     /// receive a transaction from high level
-    rx_tx: mpsc::UnboundedReceiver<Transaction>,
+    rx_tx: mpsc::UnboundedReceiver<Command>,
     /// send client info
-    tx_result: mpsc::UnboundedSender<ByteRecord>,
-    /// store client ids and its data based on transactrions it receives
-    clients: HashMap<u16, Client>,
+    tx_result: mpsc::UnboundedSender<Account>,
+    /// client accounts updated by the transactions it receives
+    engine: PaymentEngine,
+    /// options of the run
+    config: Config,
+    /// number of transactions applied successfully
+    processed: u64,
+    /// number of transactions that failed to be applied
+    rejected: u64,
+    /// expected sum of the total balances, e.g. the deposits minus the
+    /// withdrawals and chargebacks applied
+    expected_total: Decimal,
+    /// receives the balances of the client after every applied transaction
+    trace: Option<Writer<Box<dyn Write + Send>>>,
 }
 
 impl ProcessTransactionsTask {
-    /// run the task
-    async fn run(&mut self) {
-        // loop while channel is not disconected
-        loop {
-            match self.rx_tx.try_recv() {
-                Ok(tx) => {
-                    self.clients
-                        // create a new entry using client's id from transaction
-                        .entry(tx.client_id)
-                        // if the given entry has a client instance already set as value modify
-                        // the data based on the new transactions it receives
-                        .and_modify(|client| {
-                            if let Err(err) = client.process_tx(tx.tx_id, tx.tx_type, tx.tx_amount)
-                            {
-                                log::error!("Error processing transaction! {tx:?}\n{err}")
-                            }
-                        })
-                        // if there's value associated to the current client id entry create a new client
-                        .or_insert_with(|| Client::new(tx.tx_id, tx.tx_type, tx.tx_amount));
+    /// run the task, once all transactions are processed returns a report of the run.
+    ///
+    /// Fails as soon as the maximum number of rejected transactions is reached,
+    /// no further account is sent
+    async fn run(&mut self) -> Result<RunReport> {
+        // loop while channel is not disconected, waiting for the next command
+        // lets the sender run while the task is idle
+        while let Some(command) = self.rx_tx.recv().await {
+            match command {
+                Command::Tx(tx) => self.apply(tx)?,
+                Command::Checkpoint {
+                    path,
+                    records,
+                    done,
+                } => {
+                    let checkpoint = Checkpoint {
+                        records,
+                        processed: self.processed,
+                        rejected: self.rejected,
+                        engine: &self.engine,
+                    };
+                    let _ = done.send(checkpoint.save(&path));
+                }
+                Command::Snapshot { done } => {
+                    let _ = done.send(self.output_accounts().collect());
+                }
+                // the balances of a moved client leave the engine for another one
+                Command::Take { client_id, done } => {
+                    let client = self.engine.take_client(client_id);
+                    self.expected_total -= client.total();
+                    let _ = done.send(client);
+                }
+                Command::Insert(client) => {
+                    self.expected_total += client.total();
+                    self.engine.insert_client(*client);
+                }
+            }
+        }
+
+        if let Some(Err(err)) = self.trace.as_mut().map(Writer::flush) {
+            log::error!("Error in writing the trace! \n {err}");
+        }
+        // a strong correctness net, the accounts must agree with the applied
+        // transactions
+        if let Err(err) = self.engine.reconcile(self.expected_total) {
+            log::error!("{err}");
+        }
+        // after channel was dropped we can proceed to send out to high level the
+        // account balances
+        let accounts = self.send_acccount_balances();
+        if let Some(bins) = self.config.histogram {
+            self.print_histogram(bins);
+        }
+        if self.config.system_totals {
+            eprintln!("{}", self.engine.system_totals());
+        }
+        Ok(RunReport {
+            processed: self.processed,
+            rejected: self.rejected,
+            accounts,
+            ..Default::default()
+        })
+    }
+
+    /// apply a transaction counting it as processed or rejected, fails once the
+    /// maximum number of rejected transactions is reached
+    fn apply(&mut self, tx: Transaction) -> Result<()> {
+        // the transaction is moved into the engine, what's reported afterwards
+        // is copied beforehand
+        let (tx_type, client_id, tx_id) = (tx.tx_type, tx.client_id, tx.tx_id);
+        let destination_id = tx
+            .destination_id
+            .filter(|_| tx_type == TransactionEnum::Transfer);
+        match self.process(tx) {
+            Ok(change) => {
+                self.processed += 1;
+                self.expected_total += change;
+                self.trace(tx_type, tx_id, client_id, destination_id);
+            }
+            Err(err) => {
+                self.rejected += 1;
+                match self.config.log_format {
+                    LogFormat::Text => log::error!(
+                        "Error processing transaction! {tx_type} client {client_id} tx {tx_id}\n{err}"
+                    ),
+                    LogFormat::Json => Event::rejected(client_id, tx_id, &err).emit(),
                 }
-                Err(TryRecvError::Disconnected) => {
-                    // after channel was dropped we can proceed to send out to high level the
-                    // account balances
-                    return self.send_acccount_balances();
+                if self
+                    .config
+                    .max_errors
+                    .is_some_and(|max_errors| self.rejected >= max_errors)
+                {
+                    bail!(
+                        "Aborting the run, {} transactions were rejected!",
+                        self.rejected
+                    );
                 }
-                Err(TryRecvError::Empty) => {}
             }
         }
+        Ok(())
+    }
+
+    /// apply a transaction, explaining it to stderr when configured, returns
+    /// the change of the sum of the total balances it's expected to make
+    fn process(&mut self, tx: Transaction) -> anyhow::Result<Decimal> {
+        let change = self.engine.total_change(&tx);
+        if self.config.explain {
+            self.engine.process_explained(tx, &mut io::stderr())?;
+        } else {
+            self.engine.process(tx)?;
+        }
+        Ok(change)
     }
 
-    /// send account balances to high level
-    fn send_acccount_balances(&self) {
-        // for every client id get it's info and send it to high level
-        self.clients.iter().for_each(|(client_id, client)| {
-            let _ = self
-                .tx_result
-                .send(ByteRecord::from(client.get_info(client_id)));
+    /// write to the trace the balances of the clients of an applied transaction,
+    /// a transfer writes the ones of its client and of the destination
+    fn trace(
+        &mut self,
+        tx_type: TransactionEnum,
+        tx_id: u32,
+        client_id: u16,
+        destination_id: Option<u16>,
+    ) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        for client_id in iter::once(client_id).chain(destination_id) {
+            let Some(client) = self.engine.client(client_id) else {
+                continue;
+            };
+            // the record is `type,client,tx,available,held,total,locked`
+            let mut record = client
+                .snapshot(client_id)
+                .get_info(&self.config, &Column::DEFAULT);
+            record.insert(0, tx_type.to_string());
+            record.insert(2, tx_id.to_string());
+            if let Err(err) = trace.write_record(&record) {
+                log::error!("Error in writing the trace! \n {err}");
+            }
+        }
+        // the workers share the trace, the rows of a transaction are passed on
+        // at once
+        if self.config.workers > 1 {
+            if let Err(err) = trace.flush() {
+                log::error!("Error in writing the trace! \n {err}");
+            }
+        }
+    }
+
+    /// send account balances to high level, returns the number of accounts sent
+    fn send_acccount_balances(&self) -> usize {
+        self.send_accounts(self.output_accounts())
+    }
+
+    /// Returns the accounts to output, filtered and ordered as configured
+    fn output_accounts(&self) -> Box<dyn Iterator<Item = Account> + '_> {
+        let accounts = self.engine.accounts_iter().filter(|account| {
+            (!self.config.locked_only || account.locked)
+                // clients only created by rejected operations never had a balance change
+                && (!self.config.hide_inactive || self.engine.has_transactions(account.client))
         });
+        match self.config.sort_by {
+            // the accounts are streamed in client ID order
+            SortBy::Client => Box::new(accounts),
+            sort_by => {
+                let mut accounts: Vec<_> = accounts.collect();
+                sort_by.sort(&mut accounts);
+                Box::new(accounts.into_iter())
+            }
+        }
+    }
+
+    /// send the accounts to high level, returns the number of accounts sent
+    fn send_accounts(&self, mut accounts: impl Iterator<Item = Account>) -> usize {
+        let mut sent = 0;
+        // for every client get it's info and send it to high level
+        while let Some(account) = accounts.next() {
+            if self.tx_result.send(account).is_err() {
+                log::warn!(
+                    "Output was abandoned, {} accounts were not written!",
+                    1 + accounts.count()
+                );
+                break;
+            }
+            sent += 1;
+        }
+        sent
+    }
+
+    /// print the distribution of the total balances to stderr
+    fn print_histogram(&self, bins: usize) {
+        let totals: Vec<Decimal> = self
+            .engine
+            .accounts_iter()
+            .map(|account| account.total)
+            .collect();
+        eprint!("{}", Histogram::new(&totals, bins));
+    }
+}
+
+/// Summary of a processing run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RunReport {
+    /// Number of transactions applied successfully
+    pub processed: u64,
+    /// Number of transactions that failed to be applied
+    pub rejected: u64,
+    /// Number of malformed records skipped
+    pub skipped: u64,
+    /// Number of client accounts in the output
+    pub accounts: usize,
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "processed: {}, rejected: {}, skipped: {}, accounts: {}",
+            self.processed, self.rejected, self.skipped, self.accounts
+        )
+    }
+}
+
+impl RunReport {
+    /// Returns the outcome of the completed run
+    pub fn outcome(&self) -> RunOutcome {
+        match (self.rejected, self.skipped) {
+            (0, 0) => RunOutcome::Clean,
+            (rejected, skipped) => RunOutcome::Partial { rejected, skipped },
+        }
+    }
+}
+
+/// Outcome of a processing run, it tells apart a run that left out some
+/// transactions from one that applied all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// Every transaction was applied
+    Clean,
+    /// The run completed without some transactions
+    Partial {
+        /// Number of rejected transactions
+        rejected: u64,
+        /// Number of skipped malformed records
+        skipped: u64,
+    },
+    /// The run failed, e.g. it was aborted after too many rejected
+    /// transactions, the output can't be relied on
+    Aborted,
+}
+
+impl RunOutcome {
+    /// Returns the outcome of the result of a run
+    pub fn of(result: &Result<RunReport>) -> Self {
+        match result {
+            Ok(report) => report.outcome(),
+            Err(_) => RunOutcome::Aborted,
+        }
+    }
+}
+
+impl From<RunOutcome> for ExitCode {
+    /// `0` for a clean run, `2` for a partial one and `1` for a failed one
+    fn from(outcome: RunOutcome) -> Self {
+        match outcome {
+            RunOutcome::Clean => ExitCode::SUCCESS,
+            RunOutcome::Partial { .. } => ExitCode::from(2),
+            RunOutcome::Aborted => ExitCode::FAILURE,
+        }
     }
 }
 
 /// Process transactions and get client balance information
 pub(crate) struct ProcessTransactions {
     /// Send a new transaction to be processed
-    pub(crate) tx_tx: mpsc::UnboundedSender<Transaction>,
+    pub(crate) tx_tx: mpsc::UnboundedSender<Command>,
     /// Receive client's balance information based on it's transaction flow
-    pub(crate) rx_result: mpsc::UnboundedReceiver<ByteRecord>,
+    pub(crate) rx_result: mpsc::UnboundedReceiver<Account>,
+    /// Handle of the background task, resolves to the report of the run or
+    /// the reason it was aborted
+    pub(crate) handle: JoinHandle<Result<RunReport>>,
+    /// Receives the engine once the task is over, it may be reused by the next
+    /// ledger
+    pub(crate) engine: oneshot::Receiver<PaymentEngine>,
 }
 
 impl ProcessTransactions {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(config: &Config) -> Result<Self> {
+        Self::with_engine(config, PaymentEngine::with_policy(config.policy))
+    }
+
+    /// Spawns the task applying the transactions to `engine`, e.g. one that was
+    /// reset for the next ledger
+    pub(crate) fn with_engine(config: &Config, engine: PaymentEngine) -> Result<Self> {
+        Self::resume(
+            config,
+            Checkpoint {
+                records: 0,
+                processed: 0,
+                rejected: 0,
+                engine,
+            },
+        )
+    }
+
+    /// Spawns a worker of a sharded run, the rows of it's transactions are
+    /// written to the trace shared by every worker
+    pub(crate) fn worker(config: &Config, trace: Option<SharedWriter<File>>) -> Self {
+        let trace =
+            trace.map(|trace| Writer::from_writer(Box::new(trace) as Box<dyn Write + Send>));
+        Self::spawn(
+            config,
+            Checkpoint {
+                records: 0,
+                processed: 0,
+                rejected: 0,
+                engine: PaymentEngine::with_policy(config.policy),
+            },
+            trace,
+        )
+    }
+
+    /// Creates the trace shared by the workers of a sharded run, it's header
+    /// is written once
+    pub(crate) fn shared_trace(path: &Path) -> Result<SharedWriter<File>> {
+        let trace = SharedWriter::new(create_trace(path)?);
+        let mut header = Writer::from_writer(trace.clone());
+        header.write_record(TRACE_HEADER)?;
+        header.flush()?;
+        Ok(trace)
+    }
+
+    /// Spawns the task starting from the accounts of a checkpoint, the policy
+    /// of the configuration applies to them
+    pub(crate) fn resume(config: &Config, checkpoint: Checkpoint) -> Result<Self> {
+        let trace = match &config.trace {
+            Some(path) => {
+                let mut trace =
+                    Writer::from_writer(Box::new(create_trace(path)?) as Box<dyn Write + Send>);
+                trace.write_record(TRACE_HEADER)?;
+                Some(trace)
+            }
+            None => None,
+        };
+        Ok(Self::spawn(config, checkpoint, trace))
+    }
+
+    /// Spawns the task applying the transactions to the accounts of a
+    /// checkpoint, writing their rows to `trace`
+    fn spawn(
+        config: &Config,
+        checkpoint: Checkpoint,
+        trace: Option<Writer<Box<dyn Write + Send>>>,
+    ) -> Self {
         // create channels needed for comunication
         let (tx_tx, rx_tx) = mpsc::unbounded_channel();
         let (tx_result, rx_result) = mpsc::unbounded_channel();
+        let config = config.clone();
+        let mut engine = checkpoint.engine;
+        engine.set_policy(config.policy);
+        // the accounts of the checkpoint are the starting point
+        let expected_total = engine.total();
+
+        let (engine_tx, engine_rx) = oneshot::channel();
 
         // spawn a new task in background, it lives as long as ProcessTransaction
-        tokio::spawn(async move {
-            ProcessTransactionsTask {
+        let handle = tokio::spawn(async move {
+            let mut task = ProcessTransactionsTask {
                 rx_tx,
                 tx_result,
-                clients: HashMap::new(),
-            }
-            .run()
-            .await
+                engine,
+                config,
+                processed: checkpoint.processed,
+                rejected: checkpoint.rejected,
+                expected_total,
+                trace,
+            };
+            let report = task.run().await;
+            let _ = engine_tx.send(task.engine);
+            report
         });
 
-        Self { tx_tx, rx_result }
+        Self {
+            tx_tx,
+            rx_result,
+            handle,
+            engine: engine_rx,
+        }
+    }
+
+    /// Saves a checkpoint once the transactions sent so far are applied,
+    /// waiting until it's saved
+    pub(crate) async fn checkpoint(&self, path: PathBuf, records: u64) -> Result<()> {
+        let (done, saved) = oneshot::channel();
+        self.tx_tx
+            .send(Command::Checkpoint {
+                path,
+                records,
+                done,
+            })
+            .map_err(|_| anyhow!("The processing task stopped unexpectedly!"))?;
+        saved
+            .await
+            .map_err(|_| anyhow!("The processing task stopped unexpectedly!"))?
+    }
+
+    /// Removes a client once the transactions sent so far are applied,
+    /// returning it to insert it in another worker
+    pub(crate) async fn take(&self, client_id: u16) -> Result<MovedClient> {
+        let (done, client) = oneshot::channel();
+        self.tx_tx
+            .send(Command::Take { client_id, done })
+            .map_err(|_| anyhow!("The processing task stopped unexpectedly!"))?;
+        client
+            .await
+            .map_err(|_| anyhow!("The processing task stopped unexpectedly!"))
+    }
+
+    /// Returns the accounts to output once the transactions sent so far are
+    /// applied
+    pub(crate) async fn snapshot(&self) -> Result<Vec<Account>> {
+        let (done, accounts) = oneshot::channel();
+        self.tx_tx
+            .send(Command::Snapshot { done })
+            .map_err(|_| anyhow!("The processing task stopped unexpectedly!"))?;
+        accounts
+            .await
+            .map_err(|_| anyhow!("The processing task stopped unexpectedly!"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, ProcessTransactions};
+    use crate::{
+        config::Config,
+        transaction::{Transaction, TransactionEnum},
+    };
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn end_client_system_totals() {
+        let config = Config {
+            system_totals: true,
+            histogram: Some(2),
+            ..Default::default()
+        };
+        let mut process_tx = ProcessTransactions::new(&config).unwrap();
+        for (tx_type, client_id, tx_id, tx_amount) in [
+            (TransactionEnum::Deposit, 1, 1, dec!(10.0)),
+            (TransactionEnum::Deposit, 2, 2, dec!(5.0)),
+            (TransactionEnum::EndClient, 2, 3, Decimal::ZERO),
+            (TransactionEnum::Deposit, 1, 4, dec!(1.0)),
+        ] {
+            process_tx
+                .tx_tx
+                .send(Command::Tx(Transaction {
+                    tx_type,
+                    client_id,
+                    tx_id,
+                    tx_amount,
+                    timestamp: None,
+                    idempotency_key: None,
+                    destination_id: None,
+                    group_id: None,
+                }))
+                .unwrap();
+        }
+        drop(process_tx.tx_tx);
+
+        let report = process_tx.handle.await.unwrap().unwrap();
+        assert_eq!((report.processed, report.accounts), (4, 2));
+        // the totals printed agree with the accounts written, the ended one
+        // included
+        let mut total = Decimal::ZERO;
+        while let Ok(account) = process_tx.rx_result.try_recv() {
+            total += account.total;
+        }
+        let engine = process_tx.engine.await.unwrap();
+        assert_eq!(total, dec!(16.0));
+        assert_eq!(engine.system_totals().total, total);
+    }
+
+    #[tokio::test]
+    async fn closed_result_channel() {
+        crate::tests::capture_logs();
+        let process_tx = ProcessTransactions::new(&Config::default()).unwrap();
+        for client_id in 1..=3 {
+            process_tx
+                .tx_tx
+                .send(Command::Tx(Transaction {
+                    tx_type: TransactionEnum::Deposit,
+                    client_id,
+                    tx_id: client_id as u32,
+                    tx_amount: dec!(1.0),
+                    timestamp: None,
+                    idempotency_key: None,
+                    destination_id: None,
+                    group_id: None,
+                }))
+                .unwrap();
+        }
+        // the receiver is gone before the accounts are sent
+        drop(process_tx.rx_result);
+        drop(process_tx.tx_tx);
+
+        let report = process_tx.handle.await.unwrap().unwrap();
+        assert_eq!((report.processed, report.accounts), (3, 0));
+        // a single warning for every account that wasn't written
+        let warnings: Vec<_> = crate::tests::logged()
+            .into_iter()
+            .filter(|(level, _)| *level == log::Level::Warn)
+            .map(|(_, message)| message)
+            .collect();
+        assert_eq!(
+            warnings,
+            vec!["Output was abandoned, 3 accounts were not written!"]
+        );
     }
 }
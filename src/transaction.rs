@@ -1,8 +1,14 @@
-use serde::Deserialize;
+use anyhow::bail;
+use rust_decimal::Decimal;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{fmt, str::FromStr};
 
 // Type of transactions enum
 // Using aliasis in case first leter of transaction type is lowercase
-#[derive(Clone, Copy, Deserialize, Debug, PartialEq)]
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionEnum {
     Deposit,
@@ -10,30 +16,215 @@ pub enum TransactionEnum {
     Dispute,
     Resolve,
     Chargeback,
+    Reversal,
+    Transfer,
+    // marks that a client has no further transactions, its final balances are
+    // output right away
+    #[serde(rename = "end_client")]
+    EndClient,
+}
+
+impl fmt::Display for TransactionEnum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransactionEnum::Deposit => "deposit",
+            TransactionEnum::Withdrawal => "withdrawal",
+            TransactionEnum::Dispute => "dispute",
+            TransactionEnum::Resolve => "resolve",
+            TransactionEnum::Chargeback => "chargeback",
+            TransactionEnum::Reversal => "reversal",
+            TransactionEnum::Transfer => "transfer",
+            TransactionEnum::EndClient => "end_client",
+        })
+    }
 }
 
 // Holds all the information for a transaction
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
-pub(crate) struct Transaction {
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Transaction {
     // Transaction type
     #[serde(rename = "type")]
-    pub(crate) tx_type: TransactionEnum,
+    pub tx_type: TransactionEnum,
     // Client ID
     #[serde(rename = "client")]
-    pub(crate) client_id: u16,
+    pub client_id: u16,
     #[serde(rename = "tx")]
     // Transaction ID
-    pub(crate) tx_id: u32,
+    pub tx_id: u32,
     #[serde(rename = "amount")]
-    #[serde(default = "default_amount")]
+    #[serde(
+        default = "default_amount",
+        deserialize_with = "deserialize_amount",
+        serialize_with = "serialize_amount"
+    )]
     // Transaction amount
-    pub(crate) tx_amount: f32,
+    pub tx_amount: Decimal,
+    // Optional time of the transaction, orders the transactions merged from
+    // several files
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    // Optional key identifying a delivery of the transaction, a transaction
+    // replayed with the same key is applied only once
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    // Client receiving the amount of a transfer, the other transactions don't
+    // have one
+    #[serde(rename = "destination", default)]
+    pub destination_id: Option<u16>,
+    // Optional group the client is tagged into, e.g. a tenant, the balances
+    // are aggregated per group
+    #[serde(rename = "group", default)]
+    pub group_id: Option<u32>,
+}
+
+impl Transaction {
+    /// Checks the structure of a transaction independently of any account, e.g.
+    /// a deposit without an amount or a transfer without a destination could
+    /// never be applied as intended. A negative deposit is a correction when
+    /// `correction_deposits` is set
+    pub(crate) fn check_structure(&self, correction_deposits: bool) -> anyhow::Result<()> {
+        let moves_funds = matches!(
+            self.tx_type,
+            TransactionEnum::Deposit | TransactionEnum::Withdrawal | TransactionEnum::Transfer
+        );
+        let correction = correction_deposits
+            && self.tx_type == TransactionEnum::Deposit
+            && self.tx_amount < Decimal::ZERO;
+        if moves_funds && self.tx_amount <= Decimal::ZERO && !correction {
+            bail!(
+                "Transaction ID: {} has no positive amount for a {}!",
+                self.tx_id,
+                self.tx_type
+            );
+        }
+        if self.tx_type == TransactionEnum::Transfer && self.destination_id.is_none() {
+            bail!("Transfer ID: {} has no destination client!", self.tx_id);
+        }
+        Ok(())
+    }
+
+    /// Converts an amount in integer minor units, e.g. `1050` cents, to major
+    /// units, e.g. `10.50`. Fails when the amount isn't a whole number of cents
+    pub(crate) fn cents_to_units(mut self) -> anyhow::Result<Self> {
+        if !self.tx_amount.fract().is_zero() {
+            bail!(
+                "Transaction ID: {} amount {} isn't a whole number of cents!",
+                self.tx_id,
+                self.tx_amount
+            );
+        }
+        self.tx_amount /= Decimal::ONE_HUNDRED;
+        Ok(self)
+    }
+
+    /// Checks that the integer part of the amount has at most `max` digits,
+    /// absurd amounts of an adversarial input are rejected
+    pub(crate) fn check_integer_digits(self, max: u32) -> anyhow::Result<Self> {
+        let integer = self.tx_amount.abs().trunc();
+        let digits = if integer.is_zero() {
+            0
+        } else {
+            integer.to_string().len()
+        };
+        if digits > max as usize {
+            bail!(
+                "Transaction ID: {} amount {} has more than {max} integer digits!",
+                self.tx_id,
+                self.tx_amount
+            );
+        }
+        Ok(self)
+    }
+
+    /// Derives whether a deposit or withdrawal is one or the other from the sign
+    /// of its amount, for sources encoding both as a single signed amount. A
+    /// negative amount is a withdrawal of its absolute value and a positive one
+    /// a deposit, the other transactions are left as they are
+    pub(crate) fn apply_sign(&mut self) {
+        if !matches!(
+            self.tx_type,
+            TransactionEnum::Deposit | TransactionEnum::Withdrawal
+        ) || self.tx_amount.is_zero()
+        {
+            return;
+        }
+        self.tx_type = if self.tx_amount.is_sign_negative() {
+            TransactionEnum::Withdrawal
+        } else {
+            TransactionEnum::Deposit
+        };
+        self.tx_amount = self.tx_amount.abs();
+    }
 }
 
 /// Used for dispute, resolve, chargeback transactions because they
 /// don't include the amount field.
-fn default_amount() -> f32 {
-    f32::default()
+fn default_amount() -> Decimal {
+    Decimal::ZERO
+}
+
+/// Parses the amount from it's textual form so no precision is lost, e.g.
+/// `10.5` or `"1,000.50"`.
+///
+/// Thousands separators are stripped, they must group the integer part by
+/// three digits so an amount like `1,5` isn't mistaken for `15`.
+fn deserialize_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+    struct AmountVisitor;
+
+    impl<'de> Visitor<'de> for AmountVisitor {
+        type Value = Decimal;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a decimal amount")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+            // an empty field is a missing amount, e.g. a dispute in a file
+            // having more columns
+            if v.trim().is_empty() {
+                return Ok(default_amount());
+            }
+            parse_amount(v.trim()).ok_or_else(|| E::custom(format!("could not parse amount '{v}'")))
+        }
+    }
+
+    deserializer.deserialize_str(AmountVisitor)
+}
+
+/// Writes the amount in it's textual form, a missing (zero) amount is left
+/// blank like the one of a dispute
+fn serialize_amount<S: Serializer>(amount: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    if amount.is_zero() {
+        serializer.serialize_str("")
+    } else {
+        serializer.collect_str(amount)
+    }
+}
+
+/// Converts an amount written as a negative in accounting notation, e.g.
+/// `(10.00)`, to a signed one, e.g. `-10.00`. Returns `None` for any other amount
+pub(crate) fn accounting_negative(amount: &str) -> Option<String> {
+    let inner = amount.trim().strip_prefix('(')?.strip_suffix(')')?;
+    Some(format!("-{}", inner.trim()))
+}
+
+/// Parses an amount, stripping the thousands separators of the integer part
+pub(crate) fn parse_amount(amount: &str) -> Option<Decimal> {
+    if !amount.contains(',') {
+        return Decimal::from_str(amount).ok();
+    }
+
+    let integer = amount.split('.').next().unwrap_or_default();
+    let digits = integer.trim_start_matches(['-', '+']);
+    let mut groups = digits.split(',');
+    let grouped = groups
+        .next()
+        .is_some_and(|first| (1..=3).contains(&first.len()))
+        && groups.all(|group| group.len() == 3);
+    if !grouped {
+        return None;
+    }
+    Decimal::from_str(&amount.replace(',', "")).ok()
 }
 
 #[cfg(test)]
@@ -41,21 +232,18 @@ mod tests {
 
     use std::fs::File;
 
-    use super::{Transaction, TransactionEnum};
+    use super::{parse_amount, Transaction, TransactionEnum};
+    use crate::config::InputConfig;
     use anyhow::Result;
-    use csv::{ByteRecord, Reader, ReaderBuilder, Trim};
+    use csv::{ByteRecord, Reader};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
 
     fn initialize() -> Result<Reader<File>> {
         match File::open("csv_files/tx_test.csv") {
-            Ok(file) => {
-                return Ok(ReaderBuilder::new()
-                    .delimiter(b',')
-                    .flexible(true)
-                    .trim(Trim::All)
-                    .from_reader(file))
-            }
+            Ok(file) => Ok(InputConfig::default().from_reader(file)),
             Err(e) => panic!("{e}"),
-        };
+        }
     }
 
     #[test]
@@ -65,36 +253,56 @@ mod tests {
         let four_inputs = ByteRecord::from(vec!["type", "client", "tx", "amount"]);
         let three_inputs = ByteRecord::from(vec!["type", "client", "tx"]);
 
-        let compare_tx = vec![
+        let compare_tx = [
             Transaction {
                 tx_type: TransactionEnum::Deposit,
                 client_id: 1,
                 tx_id: 1,
-                tx_amount: 10.0,
+                tx_amount: dec!(10.0),
+                timestamp: None,
+                idempotency_key: None,
+                destination_id: None,
+                group_id: None,
             },
             Transaction {
                 tx_type: TransactionEnum::Withdrawal,
                 client_id: 1,
                 tx_id: 4,
-                tx_amount: 3.0,
+                tx_amount: dec!(3.0),
+                timestamp: None,
+                idempotency_key: None,
+                destination_id: None,
+                group_id: None,
             },
             Transaction {
                 tx_type: TransactionEnum::Dispute,
                 client_id: 1,
                 tx_id: 3,
-                tx_amount: 0.0,
+                tx_amount: Decimal::ZERO,
+                timestamp: None,
+                idempotency_key: None,
+                destination_id: None,
+                group_id: None,
             },
             Transaction {
                 tx_type: TransactionEnum::Resolve,
                 client_id: 1,
                 tx_id: 3,
-                tx_amount: 0.0,
+                tx_amount: Decimal::ZERO,
+                timestamp: None,
+                idempotency_key: None,
+                destination_id: None,
+                group_id: None,
             },
             Transaction {
                 tx_type: TransactionEnum::Chargeback,
                 client_id: 1,
                 tx_id: 3,
-                tx_amount: 0.0,
+                tx_amount: Decimal::ZERO,
+                timestamp: None,
+                idempotency_key: None,
+                destination_id: None,
+                group_id: None,
             },
         ];
         let mut store_tx = vec![];
@@ -118,4 +326,95 @@ mod tests {
             .enumerate()
             .for_each(|(index, tx)| assert_eq!(tx, compare_tx.get(index).unwrap()))
     }
+
+    #[test]
+    fn thousands_separator() {
+        let data = "type,client,tx,amount\ndeposit,1,1,\"1,000.50\"\ndeposit,1,2,\"1,5\"\n";
+        let mut reader = InputConfig::default().from_reader(data.as_bytes());
+        let mut txs = reader.deserialize::<Transaction>();
+
+        assert_eq!(txs.next().unwrap().unwrap().tx_amount, dec!(1000.50));
+        assert!(txs.next().unwrap().is_err());
+
+        assert_eq!(parse_amount("-12,345,678.9"), Some(dec!(-12345678.9)));
+        assert_eq!(parse_amount("1000,000"), None);
+        assert_eq!(parse_amount(",100"), None);
+    }
+
+    #[test]
+    fn equivalent_amounts() {
+        let data = "type,client,tx,amount
+deposit,1,1,10
+deposit,1,2,10.0000
+deposit,1,3,010.0
+deposit,1,4,0010
+deposit,1,5,+10.00
+deposit,1,6,10.
+deposit,1,7,\"0,010.000\"
+";
+        let amounts: Vec<Decimal> = InputConfig::default()
+            .from_reader(data.as_bytes())
+            .deserialize::<Transaction>()
+            .map(|tx| tx.unwrap().tx_amount)
+            .collect();
+        assert_eq!(amounts, vec![dec!(10); 7]);
+
+        // the representations differ only by their scale
+        assert_eq!(parse_amount("0.50"), Some(dec!(0.5)));
+        assert_eq!(parse_amount(".5"), Some(dec!(0.5)));
+        assert_eq!(parse_amount("00.5000"), Some(dec!(0.5)));
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let tx = |tx_type, tx_amount| Transaction {
+            tx_type,
+            client_id: 7,
+            tx_id: 42,
+            tx_amount,
+            timestamp: Some(3),
+            idempotency_key: None,
+            destination_id: None,
+            group_id: None,
+        };
+        let txs = [
+            tx(TransactionEnum::Deposit, dec!(10.5)),
+            tx(TransactionEnum::Withdrawal, dec!(0.0001)),
+            tx(TransactionEnum::Dispute, Decimal::ZERO),
+            tx(TransactionEnum::Resolve, Decimal::ZERO),
+            tx(TransactionEnum::Chargeback, Decimal::ZERO),
+            tx(TransactionEnum::Reversal, Decimal::ZERO),
+            Transaction {
+                destination_id: Some(8),
+                ..tx(TransactionEnum::Transfer, dec!(2.5))
+            },
+        ];
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for tx in &txs {
+            writer.serialize(tx).unwrap();
+        }
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        // the transactions without an amount leave it blank
+        assert_eq!(
+            csv.lines().take(4).collect::<Vec<_>>(),
+            vec![
+                "type,client,tx,amount,timestamp,idempotency_key,destination,group",
+                "deposit,7,42,10.5,3,,,",
+                "withdrawal,7,42,0.0001,3,,,",
+                "dispute,7,42,,3,,,",
+            ]
+        );
+        let read: Vec<Transaction> = InputConfig::default()
+            .from_reader(csv.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, txs);
+
+        for tx in txs {
+            let json = serde_json::to_string(&tx).unwrap();
+            assert_eq!(serde_json::from_str::<Transaction>(&json).unwrap(), tx);
+        }
+    }
 }